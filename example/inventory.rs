@@ -0,0 +1,119 @@
+use anyhow::Result;
+use serde_json::Value;
+use soap::{
+    action::{Action, Consequence},
+    convert::{plan_value, GoapState},
+    goal::Goal,
+    key::Key,
+    planner::PlanOptions,
+    state::State,
+};
+
+#[derive(Debug, Default, GoapState)]
+pub struct Tools {
+    axe: bool,
+    #[goap(rename = "axe_durability")]
+    durability: u32,
+}
+
+#[derive(Debug, Default, GoapState)]
+pub struct Inventory {
+    wood: u64,
+    coins: u64,
+    tools: Tools,
+    #[goap(skip)]
+    last_trade: Option<String>,
+}
+
+const WOOD: Key<u64> = Key::new("wood");
+const COINS: Key<u64> = Key::new("coins");
+const AXE: Key<bool> = Key::new("tools.axe");
+const DURABILITY: Key<u64> = Key::new("tools.axe_durability");
+
+// Chop wood, faster with an axe
+pub struct ChopAction {}
+
+impl Action for ChopAction {
+    fn key(&self) -> String {
+        "chop".to_owned()
+    }
+
+    fn options(&self, state: &State) -> Vec<(Consequence, u64)> {
+        let wood = state.value(WOOD).unwrap_or(0);
+        let durability = state.value(DURABILITY).unwrap_or(0);
+        let mut consequences = vec![(
+            Consequence {
+                action: self.key(),
+                argument: Some(Value::from("hands")),
+                result: state.with_value(WOOD, wood + 1),
+            },
+            4,
+        )];
+        if state.value(AXE).unwrap_or(false) && durability > 0 {
+            consequences.push((
+                Consequence {
+                    action: self.key(),
+                    argument: Some(Value::from("axe")),
+                    result: state
+                        .with_value(WOOD, wood + 1)
+                        .with_value(DURABILITY, durability - 1),
+                },
+                1,
+            ));
+        }
+        consequences
+    }
+}
+
+// Buy an axe
+pub struct BuyAction {}
+
+impl Action for BuyAction {
+    fn key(&self) -> String {
+        "buy".to_owned()
+    }
+
+    fn options(&self, state: &State) -> Vec<(Consequence, u64)> {
+        let coins = state.value(COINS).unwrap_or(0);
+        if coins < 2 || state.value(AXE).unwrap_or(false) {
+            return vec![];
+        }
+        vec![(
+            Consequence {
+                action: self.key(),
+                argument: Some(Value::from("axe")),
+                result: state
+                    .with_value(COINS, coins - 2)
+                    .with_value(AXE, true)
+                    .with_value(DURABILITY, 5),
+            },
+            1,
+        )]
+    }
+}
+
+fn main() -> Result<()> {
+    let start = Inventory {
+        wood: 0,
+        coins: 3,
+        tools: Tools::default(),
+        last_trade: Some("shrooms".to_owned()),
+    };
+    let goal = Goal::new().with_req(WOOD, WOOD.at_least(4));
+    let actions: Vec<Box<dyn Action>> = vec![Box::new(ChopAction {}), Box::new(BuyAction {})];
+
+    println!("Start: {:#?}", start);
+    println!("Start state: {:#?}", start.to_state());
+    println!("Goal: {:#?}", goal);
+    println!("-------------------------------------");
+    match plan_value(&start, &actions[..], &goal, &PlanOptions::new()) {
+        Some(((_, cost), end)) => {
+            println!("Cost: {}", cost);
+            println!("End: {:#?}", end);
+            println!("Last trade kept out of the state: {:?}", end.last_trade);
+        }
+        None => println!("No plan"),
+    }
+
+    Ok(())
+}
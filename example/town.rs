@@ -2,10 +2,11 @@ use anyhow::Result;
 use serde_json::{Number, Value};
 use soap::{
     action::{Action, Consequence},
-    field::Field,
+    field::{Field, FieldType},
     goal::Goal,
-    planner::plan,
+    planner::{plan_with_stats, PlanOptions},
     requirement::CompareRequirement,
+    schema::{KeySchema, Schema},
     state::State,
 };
 
@@ -17,17 +18,6 @@ impl Action for ChopAction {
         "chop".to_owned()
     }
 
-    fn prepare(&self, state: &State) -> State {
-        let mut prepared_state = state.clone();
-        if prepared_state.contains_key("wood") == false {
-            prepared_state = prepared_state.with_field("wood", Field::from(0u64));
-        }
-        if prepared_state.contains_key("axe") == false {
-            prepared_state = prepared_state.with_field("axe", Field::from(false));
-        }
-        prepared_state
-    }
-
     fn options(&self, state: &State) -> Vec<(Consequence, u64)> {
         let wood = state.get_as_u64("wood").unwrap_or(0);
         let axe = state.get_as_bool("axe").unwrap_or(false);
@@ -65,14 +55,6 @@ impl Action for CollectAction {
         "collect".to_owned()
     }
 
-    fn prepare(&self, state: &State) -> State {
-        let mut prepared_state = state.clone();
-        if prepared_state.contains_key("shrooms") == false {
-            prepared_state = prepared_state.with_field("shrooms", Field::from(0u64));
-        }
-        prepared_state
-    }
-
     fn options(&self, state: &State) -> Vec<(Consequence, u64)> {
         let shrooms = state.get_as_u64("shrooms").unwrap_or(0);
 
@@ -95,23 +77,6 @@ impl Action for BuyAction {
         "buy".to_owned()
     }
 
-    fn prepare(&self, state: &State) -> State {
-        let mut prepared_state = state.clone();
-        if prepared_state.contains_key("coins") == false {
-            prepared_state = prepared_state.with_field("coins", Field::from(0u64));
-        }
-        if prepared_state.contains_key("axe") == false {
-            prepared_state = prepared_state.with_field("axe", Field::from(false));
-        }
-        if prepared_state.contains_key("shrooms") == false {
-            prepared_state = prepared_state.with_field("shrooms", Field::from(0u64));
-        }
-        if prepared_state.contains_key("wood") == false {
-            prepared_state = prepared_state.with_field("wood", Field::from(0u64));
-        }
-        prepared_state
-    }
-
     fn options(&self, state: &State) -> Vec<(Consequence, u64)> {
         let coins = state.get_as_u64("coins").unwrap_or(0);
 
@@ -141,23 +106,6 @@ impl Action for SellAction {
         "sell".to_owned()
     }
 
-    fn prepare(&self, state: &State) -> State {
-        let mut prepared_state = state.clone();
-        if prepared_state.contains_key("coins") == false {
-            prepared_state = prepared_state.with_field("coins", Field::from(0u64));
-        }
-        if prepared_state.contains_key("axe") == false {
-            prepared_state = prepared_state.with_field("axe", Field::from(false));
-        }
-        if prepared_state.contains_key("shrooms") == false {
-            prepared_state = prepared_state.with_field("shrooms", Field::from(0u64));
-        }
-        if prepared_state.contains_key("wood") == false {
-            prepared_state = prepared_state.with_field("wood", Field::from(0u64));
-        }
-        prepared_state
-    }
-
     fn options(&self, state: &State) -> Vec<(Consequence, u64)> {
         let coins = state.get_as_u64("coins").unwrap_or(0);
         let wood = state.get_as_u64("wood").unwrap_or(0);
@@ -249,13 +197,41 @@ fn main() -> Result<()> {
         Box::new(CollectAction {}),
     ];
 
+    let schema = Schema::new()
+        .with_key(
+            "wood",
+            KeySchema::new(FieldType::U64)
+                .with_default(Field::from(0u64))
+                .with_description("Chopped wood"),
+        )
+        .with_key(
+            "shrooms",
+            KeySchema::new(FieldType::U64)
+                .with_default(Field::from(0u64))
+                .with_description("Collected shrooms"),
+        )
+        .with_key(
+            "coins",
+            KeySchema::new(FieldType::U64)
+                .with_default(Field::from(0u64))
+                .with_description("Coins from selling"),
+        )
+        .with_key(
+            "axe",
+            KeySchema::new(FieldType::Bool)
+                .with_default(Field::from(false))
+                .with_description("Whether an axe was bought"),
+        );
+    let options = PlanOptions::new().with_schema(schema);
+
     println!("Start: {:#?}", start);
     println!("Goal: {:#?}", goal);
     println!("-------------------------------------");
     let start_time = std::time::Instant::now();
-    let plan = plan(&start, &actions[..], &goal);
+    let (plan, stats) = plan_with_stats(&start, &actions[..], &goal, &options);
     let done_in = std::time::Instant::now().duration_since(start_time);
     println!("Plan: {:#?}", plan);
+    println!("Stats: {:#?}", stats);
     println!(
         "Done in {} ms ({} μs)",
         done_in.as_millis(),
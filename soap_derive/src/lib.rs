@@ -0,0 +1,128 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    parse_macro_input, parse_quote, Data, DeriveInput, Error, Fields, GenericParam, Lit, Meta,
+    NestedMeta, Result,
+};
+
+// Implements `soap::convert::GoapState` for structs with named fields. Every
+// field has to implement it too, nested structs are stored under dotted
+// keys. Fields take `#[goap(rename = "key")]` and `#[goap(skip)]`, skipped
+// fields are read back as their default
+#[proc_macro_derive(GoapState, attributes(goap))]
+pub fn derive_goap_state(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(input) {
+        Ok(tokens) => tokens.into(),
+        Err(error) => error.to_compile_error().into(),
+    }
+}
+
+struct FieldOptions {
+    key: String,
+    skip: bool,
+}
+
+fn expand(mut input: DeriveInput) -> Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => fields.named.iter().collect::<Vec<_>>(),
+            _ => {
+                return Err(Error::new_spanned(
+                    &input.ident,
+                    "GoapState can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(Error::new_spanned(
+                &input.ident,
+                "GoapState can only be derived for structs",
+            ))
+        }
+    };
+
+    let mut writes = vec![];
+    let mut reads = vec![];
+    for field in fields {
+        let ident = field.ident.as_ref().unwrap();
+        let ty = &field.ty;
+        let options = field_options(field)?;
+        if options.skip {
+            reads.push(quote! {
+                #ident: ::std::default::Default::default()
+            });
+            continue;
+        }
+        let key = options.key;
+        writes.push(quote! {
+            ::soap::convert::GoapState::write_state(
+                &self.#ident,
+                &::soap::convert::join_key(prefix, #key),
+                state,
+            );
+        });
+        reads.push(quote! {
+            #ident: <#ty as ::soap::convert::GoapState>::read_state(
+                state,
+                &::soap::convert::join_key(prefix, #key),
+            )?
+        });
+    }
+
+    for param in input.generics.params.iter_mut() {
+        if let GenericParam::Type(param) = param {
+            param.bounds.push(parse_quote!(::soap::convert::GoapState));
+        }
+    }
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::soap::convert::GoapState for #name #ty_generics #where_clause {
+            fn write_state(&self, prefix: &str, state: &mut ::soap::state::State) {
+                #(#writes)*
+            }
+
+            fn read_state(state: &::soap::state::State, prefix: &str) -> ::std::option::Option<Self> {
+                ::std::option::Option::Some(#name {
+                    #(#reads,)*
+                })
+            }
+        }
+    })
+}
+
+fn field_options(field: &syn::Field) -> Result<FieldOptions> {
+    let mut options = FieldOptions {
+        key: field.ident.as_ref().unwrap().to_string(),
+        skip: false,
+    };
+    for attr in field.attrs.iter().filter(|attr| attr.path.is_ident("goap")) {
+        let list = match attr.parse_meta()? {
+            Meta::List(list) => list,
+            meta => return Err(Error::new_spanned(meta, "expected #[goap(...)]")),
+        };
+        for nested in list.nested {
+            match nested {
+                NestedMeta::Meta(Meta::Path(path)) if path.is_ident("skip") => {
+                    options.skip = true;
+                }
+                NestedMeta::Meta(Meta::NameValue(pair)) if pair.path.is_ident("rename") => {
+                    match pair.lit {
+                        Lit::Str(key) => options.key = key.value(),
+                        lit => return Err(Error::new_spanned(lit, "expected a string")),
+                    }
+                }
+                nested => {
+                    return Err(Error::new_spanned(
+                        nested,
+                        "expected `skip` or `rename = \"...\"`",
+                    ))
+                }
+            }
+        }
+    }
+    Ok(options)
+}
@@ -10,6 +10,20 @@ pub struct Consequence {
     pub result: State,
 }
 
+impl Consequence {
+    pub fn label(&self) -> String {
+        step_label(&self.action, self.argument.as_ref())
+    }
+}
+
+// `action(argument)`, or just the action without an argument
+pub fn step_label(action: &str, argument: Option<&Value>) -> String {
+    match argument {
+        Some(argument) => format!("{}({})", action, argument),
+        None => action.to_owned(),
+    }
+}
+
 impl PartialOrd for Consequence {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         self.result.partial_cmp(&other.result)
@@ -31,8 +45,90 @@ impl Hash for Consequence {
     }
 }
 
+// One possible result of a choice, `probability` over all outcomes of the
+// choice should add up to 1
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    pub consequence: Consequence,
+    pub probability: f64,
+}
+
 pub trait Action {
     fn key(&self) -> String;
-    fn prepare(&self, state: &State) -> State;
+    // Fills in the keys the action needs, not needed when a `Schema` with
+    // their defaults is planned with
+    fn prepare(&self, state: &State) -> State {
+        state.clone()
+    }
     fn options(&self, state: &State) -> Vec<(Consequence, u64)>;
+    // Keys the action depends on, declared up front for domain analysis
+    fn reads(&self) -> Vec<String> {
+        vec![]
+    }
+    // Keys the action may change, declared up front for domain analysis
+    fn writes(&self) -> Vec<String> {
+        vec![]
+    }
+    // Time it takes to carry out one of the action's consequences
+    fn duration(&self, _consequence: &Consequence, cost: u64) -> u64 {
+        cost
+    }
+    // Resources held while a consequence is carried out, steps sharing a
+    // resource never overlap in a schedule
+    fn resources(&self, _consequence: &Consequence) -> Vec<String> {
+        vec![]
+    }
+    // Keys whose value becomes known once the action was carried out, a
+    // conditional plan branches on them
+    fn observes(&self) -> Vec<String> {
+        vec![]
+    }
+    // Choices with uncertain results, each with its possible outcomes and
+    // its cost. Every option is a certain outcome by default
+    fn outcomes(&self, state: &State) -> Vec<(Vec<Outcome>, u64)> {
+        self.options(state)
+            .into_iter()
+            .map(|(consequence, cost)| {
+                (
+                    vec![Outcome {
+                        consequence,
+                        probability: 1.0,
+                    }],
+                    cost,
+                )
+            })
+            .collect()
+    }
+}
+
+pub type SyncAction<'a> = Box<dyn Action + Send + Sync + 'a>;
+
+impl<T: Action + ?Sized> Action for &T {
+    fn key(&self) -> String {
+        (**self).key()
+    }
+    fn prepare(&self, state: &State) -> State {
+        (**self).prepare(state)
+    }
+    fn options(&self, state: &State) -> Vec<(Consequence, u64)> {
+        (**self).options(state)
+    }
+    fn reads(&self) -> Vec<String> {
+        (**self).reads()
+    }
+    fn writes(&self) -> Vec<String> {
+        (**self).writes()
+    }
+    fn duration(&self, consequence: &Consequence, cost: u64) -> u64 {
+        (**self).duration(consequence, cost)
+    }
+    fn resources(&self, consequence: &Consequence) -> Vec<String> {
+        (**self).resources(consequence)
+    }
+    fn observes(&self) -> Vec<String> {
+        (**self).observes()
+    }
+    fn outcomes(&self, state: &State) -> Vec<(Vec<Outcome>, u64)> {
+        (**self).outcomes(state)
+    }
 }
@@ -0,0 +1,247 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+
+use crate::{
+    action::Action,
+    field::FieldType,
+    goal::Goal,
+    planner::prepare_with_schema,
+    schema::Schema,
+    state::{State, StateKey},
+};
+
+#[derive(Clone, Debug)]
+pub enum DomainWarning {
+    UnwrittenGoalKey(String),
+    ConflictingTypes {
+        key: String,
+        types: BTreeMap<String, FieldType>,
+    },
+    ZeroCostCycle(Vec<String>),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DomainReport {
+    // Key -> actions reading or writing it
+    pub reads: BTreeMap<String, BTreeSet<String>>,
+    pub writes: BTreeMap<String, BTreeSet<String>>,
+    // Key -> action -> type its `prepare` initializes the key to
+    pub initial_types: BTreeMap<String, BTreeMap<String, FieldType>>,
+    // Action keys along each zero-cost cycle found while probing
+    pub zero_cost_cycles: Vec<Vec<String>>,
+    pub explored_states: usize,
+}
+
+impl DomainReport {
+    pub fn unwritten_keys(&self, goal: &Goal) -> Vec<String> {
+        goal.requirements()
+            .keys()
+            .filter(|key| !self.writes.contains_key(*key))
+            .cloned()
+            .collect()
+    }
+
+    pub fn type_conflicts(&self) -> Vec<(String, BTreeMap<String, FieldType>)> {
+        self.initial_types
+            .iter()
+            .filter(|(_, types)| types.values().collect::<BTreeSet<_>>().len() > 1)
+            .map(|(key, types)| (key.clone(), types.clone()))
+            .collect()
+    }
+
+    pub fn warnings(&self, goal: Option<&Goal>) -> Vec<DomainWarning> {
+        let mut warnings = vec![];
+        if let Some(goal) = goal {
+            for key in self.unwritten_keys(goal) {
+                warnings.push(DomainWarning::UnwrittenGoalKey(key));
+            }
+        }
+        for (key, types) in self.type_conflicts() {
+            warnings.push(DomainWarning::ConflictingTypes { key, types });
+        }
+        for cycle in &self.zero_cost_cycles {
+            warnings.push(DomainWarning::ZeroCostCycle(cycle.clone()));
+        }
+        warnings
+    }
+}
+
+pub struct DomainAnalyzer<'a, 'b> {
+    actions: &'b [Box<dyn Action + 'a>],
+    samples: Vec<State>,
+    max_states: usize,
+    schema: Option<Schema>,
+}
+
+impl<'a, 'b> DomainAnalyzer<'a, 'b> {
+    pub fn new(actions: &'b [Box<dyn Action + 'a>]) -> Self {
+        DomainAnalyzer {
+            actions,
+            samples: vec![],
+            max_states: 1000,
+            schema: None,
+        }
+    }
+
+    // States to start probing from, the empty state is used when none are given
+    pub fn with_sample(mut self, state: State) -> Self {
+        self.samples.push(state);
+        self
+    }
+
+    // Zero disables probing, leaving only the declared reads and writes
+    pub fn with_max_states(mut self, max_states: usize) -> Self {
+        self.max_states = max_states;
+        self
+    }
+
+    // Samples get the schema's defaults before the actions prepare them
+    pub fn with_schema(mut self, schema: Schema) -> Self {
+        self.schema = Some(schema);
+        self
+    }
+
+    pub fn analyze(&self) -> DomainReport {
+        let mut report = DomainReport::default();
+
+        // Declared sets
+        for action in self.actions {
+            for key in action.reads() {
+                insert(&mut report.reads, key, action.key());
+            }
+            for key in action.writes() {
+                insert(&mut report.writes, key, action.key());
+            }
+        }
+
+        // Keys each action initializes on its own
+        let empty = State::new();
+        for action in self.actions {
+            for (key, value) in action.prepare(&empty).iter() {
+                insert(&mut report.reads, key.clone(), action.key());
+                report
+                    .initial_types
+                    .entry(key.clone())
+                    .or_default()
+                    .insert(action.key(), value.field_type());
+            }
+        }
+
+        self.probe(&mut report);
+        report
+    }
+
+    fn probe(&self, report: &mut DomainReport) {
+        let samples = if self.samples.is_empty() {
+            vec![State::new()]
+        } else {
+            self.samples.clone()
+        };
+
+        let mut states: Vec<State> = vec![];
+        let mut indices: HashMap<StateKey, usize> = HashMap::new();
+        let mut zero_cost_edges: Vec<Vec<(usize, String)>> = vec![];
+        let mut queue = VecDeque::new();
+        for sample in samples {
+            let sample = prepare_with_schema(&sample, self.actions, self.schema.as_ref());
+            let next = states.len();
+            if *indices.entry(StateKey::new(&sample)).or_insert(next) == next {
+                queue.push_back(next);
+                states.push(sample);
+                zero_cost_edges.push(vec![]);
+            }
+        }
+
+        while let Some(index) = queue.pop_front() {
+            if report.explored_states >= self.max_states {
+                break;
+            }
+            report.explored_states += 1;
+            for action in self.actions {
+                for (consequence, cost) in action.options(&states[index]) {
+                    for (key, value) in consequence.result.iter() {
+                        if states[index].get(key).as_ref() != Some(value) {
+                            insert(&mut report.writes, key.clone(), action.key());
+                        }
+                    }
+                    let key = StateKey::new(&consequence.result);
+                    let next = match indices.get(&key) {
+                        Some(&next) => next,
+                        None => {
+                            let next = states.len();
+                            indices.insert(key, next);
+                            states.push(consequence.result);
+                            zero_cost_edges.push(vec![]);
+                            queue.push_back(next);
+                            next
+                        }
+                    };
+                    if cost == 0 {
+                        zero_cost_edges[index].push((next, action.key()));
+                    }
+                }
+            }
+        }
+
+        report.zero_cost_cycles = find_cycles(&zero_cost_edges);
+    }
+}
+
+fn insert(map: &mut BTreeMap<String, BTreeSet<String>>, key: String, action: String) {
+    map.entry(key).or_default().insert(action);
+}
+
+// Depth-first search over the zero-cost edges, reporting each distinct
+// sequence of actions that leads back to a state on the current path
+fn find_cycles(edges: &[Vec<(usize, String)>]) -> Vec<Vec<String>> {
+    let mut cycles: BTreeSet<Vec<String>> = BTreeSet::new();
+    let mut visited = vec![false; edges.len()];
+    for root in 0..edges.len() {
+        if visited[root] || edges[root].is_empty() {
+            continue;
+        }
+        let mut path: Vec<(usize, String)> = vec![];
+        let mut on_path = vec![false; edges.len()];
+        let mut stack = vec![(root, 0)];
+        on_path[root] = true;
+        visited[root] = true;
+        while let Some((node, edge)) = stack.pop() {
+            if edge >= edges[node].len() {
+                on_path[node] = false;
+                path.pop();
+                continue;
+            }
+            stack.push((node, edge + 1));
+            let (next, action) = &edges[node][edge];
+            if on_path[*next] {
+                let start = stack.iter().position(|(node, _)| node == next).unwrap_or(0);
+                let mut cycle: Vec<String> = path[start..]
+                    .iter()
+                    .map(|(_, action)| action.clone())
+                    .collect();
+                cycle.push(action.clone());
+                cycles.insert(cycle);
+            } else if !visited[*next] {
+                visited[*next] = true;
+                on_path[*next] = true;
+                path.push((*next, action.clone()));
+                stack.push((*next, 0));
+            }
+        }
+    }
+    cycles.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::Walk;
+
+    #[test]
+    fn states_holding_json_values_are_told_apart() {
+        let actions: Vec<Box<dyn Action>> = vec![Box::new(Walk(5))];
+        let report = DomainAnalyzer::new(&actions).analyze();
+        assert_eq!(report.explored_states, 25);
+        assert!(report.writes["position"].contains("walk"));
+        assert!(report.zero_cost_cycles.is_empty());
+    }
+}
@@ -0,0 +1,657 @@
+use serde_json::Value;
+
+use crate::{
+    action::{step_label, Action, Consequence},
+    field::Field,
+    goal::Goal,
+    planner::{plan_with, prepare_checked, prepare_with, Node, Plan, PlanOptions},
+    state::State,
+    validation::{replay_step, same_consequence},
+};
+
+// Every state the world might be in, with its probability
+#[derive(Clone, Debug)]
+pub struct BeliefState {
+    worlds: Vec<(State, f64)>,
+}
+
+impl Default for BeliefState {
+    fn default() -> Self {
+        BeliefState::new()
+    }
+}
+
+impl BeliefState {
+    pub fn new() -> Self {
+        BeliefState { worlds: vec![] }
+    }
+
+    pub fn certain(state: State) -> Self {
+        BeliefState {
+            worlds: vec![(state, 1.0)],
+        }
+    }
+
+    // Probabilities of states with the same fields add up
+    pub fn with_world(mut self, state: State, probability: f64) -> Self {
+        match self
+            .worlds
+            .iter_mut()
+            .find(|(other, _)| same_fields(other, &state))
+        {
+            Some((_, other_probability)) => *other_probability += probability,
+            None => self.worlds.push((state, probability)),
+        }
+        self
+    }
+
+    // Splits every world into one per possible value of `key`
+    pub fn with_unknown<S: AsRef<str>>(self, key: S, values: Vec<(Field, f64)>) -> Self {
+        let worlds = if self.worlds.is_empty() {
+            vec![(State::new(), 1.0)]
+        } else {
+            self.worlds
+        };
+        let mut belief = BeliefState::new();
+        for (state, probability) in worlds {
+            for (value, value_probability) in &values {
+                belief = belief.with_world(
+                    state.with_field(key.as_ref(), value.clone()),
+                    probability * value_probability,
+                );
+            }
+        }
+        belief
+    }
+
+    pub fn worlds(&self) -> &[(State, f64)] {
+        &self.worlds
+    }
+
+    pub fn len(&self) -> usize {
+        self.worlds.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.worlds.is_empty()
+    }
+
+    pub fn is_certain(&self) -> bool {
+        self.worlds.len() == 1
+    }
+
+    // The first of the most likely worlds
+    pub fn most_likely(&self) -> Option<&State> {
+        let mut best: Option<&(State, f64)> = None;
+        for world in &self.worlds {
+            match best {
+                Some(best) if world.1 <= best.1 => {}
+                _ => best = Some(world),
+            }
+        }
+        best.map(|(state, _)| state)
+    }
+
+    pub fn probability<S: AsRef<str>>(&self, key: S, value: &Field) -> f64 {
+        self.worlds
+            .iter()
+            .filter(|(state, _)| state.get(key.as_ref()).as_ref() == Some(value))
+            .map(|(_, probability)| probability)
+            .sum()
+    }
+
+    pub fn probability_of_goal(&self, goal: &Goal) -> f64 {
+        self.worlds
+            .iter()
+            .filter(|(state, _)| state.distance_to_goal(goal) == 0)
+            .map(|(_, probability)| probability)
+            .sum()
+    }
+
+    // Keeps the worlds where `key` has the observed value, `None` meaning
+    // the key is missing. Returns `None` if no world is left
+    pub fn observe<S: AsRef<str>>(&self, key: S, value: Option<&Field>) -> Option<BeliefState> {
+        let worlds: Vec<(State, f64)> = self
+            .worlds
+            .iter()
+            .filter(|(state, _)| state.get(key.as_ref()).as_ref() == value)
+            .cloned()
+            .collect();
+        if worlds.is_empty() {
+            None
+        } else {
+            Some(BeliefState { worlds }.normalized())
+        }
+    }
+
+    // One belief per value `key` might be observed with, in the order the
+    // values first occur
+    pub fn split<S: AsRef<str>>(&self, key: S) -> Vec<(Option<Field>, BeliefState)> {
+        let mut values: Vec<Option<Field>> = vec![];
+        for (state, _) in &self.worlds {
+            let value = state.get(key.as_ref());
+            if !values.contains(&value) {
+                values.push(value);
+            }
+        }
+        values
+            .into_iter()
+            .filter_map(|value| {
+                let belief = self.observe(key.as_ref(), value.as_ref())?;
+                Some((value, belief))
+            })
+            .collect()
+    }
+
+    pub fn prepare<'a>(&self, actions: &[Box<dyn Action + 'a>]) -> BeliefState {
+        self.prepare_with(actions, &PlanOptions::new())
+    }
+
+    // Prepares every world like `prepare_with`
+    pub fn prepare_with<'a>(
+        &self,
+        actions: &[Box<dyn Action + 'a>],
+        options: &PlanOptions,
+    ) -> BeliefState {
+        let mut belief = BeliefState::new();
+        for (state, probability) in &self.worlds {
+            belief = belief.with_world(prepare_with(state, actions, options), *probability);
+        }
+        belief
+    }
+
+    // Carries out an option of `action` with `argument` in every world,
+    // `option` is its position among the action's options with that
+    // argument. Returns `None` unless it's offered in all of them, the cost
+    // is the highest over all worlds
+    pub fn apply<'a>(
+        &self,
+        actions: &[Box<dyn Action + 'a>],
+        action: &str,
+        argument: &Option<Value>,
+        option: usize,
+    ) -> Option<(BeliefState, u64)> {
+        let action = actions.iter().find(|other| other.key() == action)?;
+        let mut belief = BeliefState::new();
+        let mut cost = 0;
+        for (state, probability) in &self.worlds {
+            let (consequence, option_cost) = action
+                .options(state)
+                .into_iter()
+                .filter(|(consequence, _)| consequence.argument == *argument)
+                .nth(option)?;
+            belief = belief.with_world(consequence.result, *probability);
+            cost = cost.max(option_cost);
+        }
+        Some((belief, cost))
+    }
+
+    fn normalized(mut self) -> Self {
+        let total: f64 = self.worlds.iter().map(|(_, probability)| probability).sum();
+        if total > 0.0 {
+            for (_, probability) in &mut self.worlds {
+                *probability /= total;
+            }
+        }
+        self
+    }
+
+    fn same_worlds(&self, other: &BeliefState) -> bool {
+        self.worlds.len() == other.worlds.len()
+            && self.worlds.iter().all(|(state, _)| {
+                other
+                    .worlds
+                    .iter()
+                    .any(|(other, _)| same_fields(other, state))
+            })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum ConditionalPlan {
+    Done,
+    Step {
+        action: String,
+        argument: Option<Value>,
+        // Position among the action's options with the same argument
+        option: usize,
+        cost: u64,
+        then: Box<ConditionalPlan>,
+    },
+    // Continues with the branch of the observed value of `key`
+    Branch {
+        key: String,
+        branches: Vec<(Option<Field>, ConditionalPlan)>,
+    },
+}
+
+impl ConditionalPlan {
+    // Cost of the most expensive branch
+    pub fn cost(&self) -> u64 {
+        match self {
+            ConditionalPlan::Done => 0,
+            ConditionalPlan::Step { cost, then, .. } => cost + then.cost(),
+            ConditionalPlan::Branch { branches, .. } => branches
+                .iter()
+                .map(|(_, plan)| plan.cost())
+                .max()
+                .unwrap_or(0),
+        }
+    }
+
+    pub fn branch(&self, value: Option<&Field>) -> Option<&ConditionalPlan> {
+        match self {
+            ConditionalPlan::Branch { branches, .. } => branches
+                .iter()
+                .find(|(other, _)| other.as_ref() == value)
+                .map(|(_, plan)| plan),
+            _ => None,
+        }
+    }
+
+    // One line per step and branch, indented by nesting
+    pub fn describe(&self) -> String {
+        let mut description = String::new();
+        self.describe_into(&mut description, 0);
+        description
+    }
+
+    fn describe_into(&self, description: &mut String, indent: usize) {
+        let padding = "  ".repeat(indent);
+        match self {
+            ConditionalPlan::Done => description.push_str(&format!("{}done\n", padding)),
+            ConditionalPlan::Step {
+                action,
+                argument,
+                cost,
+                then,
+                ..
+            } => {
+                description.push_str(&format!(
+                    "{}{} [{}]\n",
+                    padding,
+                    step_label(action, argument.as_ref()),
+                    cost
+                ));
+                then.describe_into(description, indent);
+            }
+            ConditionalPlan::Branch { key, branches } => {
+                for (value, plan) in branches {
+                    description.push_str(&format!("{}if {} = {:?}:\n", padding, key, value));
+                    plan.describe_into(description, indent + 1);
+                }
+            }
+        }
+    }
+}
+
+// AND-OR search over beliefs, branching after every action that observes
+// keys the worlds disagree on. Tries every choice up to `max_depth` steps
+// per branch and keeps the plan with the lowest expected cost. An action is
+// only used where every world offers it
+pub fn plan_conditional<'a>(
+    start: &BeliefState,
+    actions: &[Box<dyn Action + 'a>],
+    goal: &Goal,
+    max_depth: usize,
+) -> Option<ConditionalPlan> {
+    plan_conditional_with(start, actions, goal, &PlanOptions::new(), max_depth)
+}
+
+// Uses the schema of the options like `plan_with`, a choice is left out
+// when its result violates the constraints or schema bounds in any world
+pub fn plan_conditional_with<'a>(
+    start: &BeliefState,
+    actions: &[Box<dyn Action + 'a>],
+    goal: &Goal,
+    options: &PlanOptions,
+    max_depth: usize,
+) -> Option<ConditionalPlan> {
+    if !options.fits_schema(goal) {
+        return None;
+    }
+    let mut prepared = BeliefState::new();
+    for (state, probability) in start.worlds() {
+        prepared = prepared.with_world(prepare_checked(state, actions, options)?, *probability);
+    }
+    let start = prepared;
+    if start.is_empty() {
+        return None;
+    }
+    let search = ConditionalSearch {
+        actions,
+        goal,
+        options,
+    };
+    let (plan, _) = search.search(&start, max_depth, &mut vec![])?;
+    Some(plan)
+}
+
+struct ConditionalSearch<'s, 'a> {
+    actions: &'s [Box<dyn Action + 'a>],
+    goal: &'s Goal,
+    options: &'s PlanOptions,
+}
+
+impl<'s, 'a> ConditionalSearch<'s, 'a> {
+    // Returns the plan with its expected cost
+    fn search(
+        &self,
+        belief: &BeliefState,
+        depth: usize,
+        path: &mut Vec<BeliefState>,
+    ) -> Option<(ConditionalPlan, f64)> {
+        if belief
+            .worlds
+            .iter()
+            .all(|(state, _)| state.distance_to_goal(self.goal) == 0)
+        {
+            return Some((ConditionalPlan::Done, 0.0));
+        }
+        if depth == 0 || path.iter().any(|other| other.same_worlds(belief)) {
+            return None;
+        }
+
+        // Options are told apart by their position among the options with
+        // the same argument, the same position may have another result in
+        // each world
+        let mut choices: Vec<(String, Option<Value>, usize)> = vec![];
+        for (state, _) in &belief.worlds {
+            for action in self.actions {
+                let options = action.options(state);
+                for (index, (consequence, _)) in options.iter().enumerate() {
+                    let option = options[..index]
+                        .iter()
+                        .filter(|(other, _)| other.argument == consequence.argument)
+                        .count();
+                    let choice = (
+                        consequence.action.clone(),
+                        consequence.argument.clone(),
+                        option,
+                    );
+                    if !choices.contains(&choice) {
+                        choices.push(choice);
+                    }
+                }
+            }
+        }
+
+        path.push(belief.clone());
+        let mut best: Option<(ConditionalPlan, f64)> = None;
+        for (action, argument, option) in choices {
+            let (next, cost) = match belief.apply(self.actions, &action, &argument, option) {
+                Some(applied) => applied,
+                None => continue,
+            };
+            if next
+                .worlds
+                .iter()
+                .any(|(state, _)| self.options.check(state).is_some())
+            {
+                continue;
+            }
+            let observed = self
+                .actions
+                .iter()
+                .find(|other| other.key() == action)
+                .map(|action| action.observes())
+                .unwrap_or_default();
+            if let Some((then, then_cost)) = self.branch(&next, &observed, depth - 1, path) {
+                let expected = cost as f64 + then_cost;
+                if !matches!(&best, Some((_, best)) if expected >= *best) {
+                    let step = ConditionalPlan::Step {
+                        action,
+                        argument,
+                        option,
+                        cost,
+                        then: Box::new(then),
+                    };
+                    best = Some((step, expected));
+                }
+            }
+        }
+        path.pop();
+        best
+    }
+
+    fn branch(
+        &self,
+        belief: &BeliefState,
+        keys: &[String],
+        depth: usize,
+        path: &mut Vec<BeliefState>,
+    ) -> Option<(ConditionalPlan, f64)> {
+        let (key, rest) = match keys.split_first() {
+            Some(split) => split,
+            None => return self.search(belief, depth, path),
+        };
+        let split = belief.split(key);
+        if split.len() <= 1 {
+            return self.branch(belief, rest, depth, path);
+        }
+
+        let total: f64 = belief
+            .worlds
+            .iter()
+            .map(|(_, probability)| probability)
+            .sum();
+        let mut branches = vec![];
+        let mut expected = 0.0;
+        for (value, branch_belief) in split {
+            let weight: f64 = belief
+                .worlds
+                .iter()
+                .filter(|(state, _)| state.get(key) == value)
+                .map(|(_, probability)| probability)
+                .sum();
+            let (plan, cost) = self.branch(&branch_belief, rest, depth, path)?;
+            if total > 0.0 {
+                expected += weight / total * cost;
+            }
+            branches.push((value, plan));
+        }
+        Some((
+            ConditionalPlan::Branch {
+                key: key.clone(),
+                branches,
+            },
+            expected,
+        ))
+    }
+}
+
+// Plans for the most likely world only, and replans whenever an
+// observation or a carried out step changes which world that is
+pub struct MostLikelyPlanner {
+    belief: BeliefState,
+    goal: Goal,
+    options: PlanOptions,
+    assumed: Option<State>,
+    plan: Option<Plan>,
+}
+
+impl MostLikelyPlanner {
+    pub fn new(belief: BeliefState, goal: Goal) -> Self {
+        MostLikelyPlanner {
+            belief,
+            goal,
+            options: PlanOptions::new(),
+            assumed: None,
+            plan: None,
+        }
+    }
+
+    pub fn with_options(mut self, options: PlanOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn belief(&self) -> &BeliefState {
+        &self.belief
+    }
+
+    // The current plan, replanned first if it was invalidated
+    pub fn plan<'a>(&mut self, actions: &[Box<dyn Action + 'a>]) -> Option<&Plan> {
+        if self.plan.is_none() {
+            let belief = self.belief.prepare_with(actions, &self.options);
+            let assumed = belief.most_likely()?.clone();
+            debug!("----- Planning for the most likely state -----");
+            self.plan = plan_with(&assumed, actions, &self.goal, &self.options);
+            self.assumed = Some(assumed);
+            self.belief = belief;
+        }
+        self.plan.as_ref()
+    }
+
+    // Narrows the belief down to the observed value. Returns whether the
+    // plan has to be replanned, which is also the case when the observation
+    // contradicts every world and the belief is left unchanged
+    pub fn observe<S: AsRef<str>>(&mut self, key: S, value: Option<&Field>) -> bool {
+        match self.belief.observe(key, value) {
+            Some(belief) => self.belief = belief,
+            None => {
+                self.invalidate();
+                return true;
+            }
+        }
+        let still_assumed = match (self.belief.most_likely(), &self.assumed) {
+            (Some(likely), Some(assumed)) => same_fields(likely, assumed),
+            _ => false,
+        };
+        if !still_assumed {
+            self.invalidate();
+        }
+        self.plan.is_none()
+    }
+
+    // Moves the belief along a step that was carried out in the world the
+    // plan assumes, or the most likely one without a plan. Every other
+    // world takes the option at the same position among the action's
+    // options with the same argument. The rest of the plan is kept if it
+    // started with that step and the most likely world is still the one it
+    // was planned for. Returns whether the plan has to be replanned
+    pub fn execute<'a>(
+        &mut self,
+        actions: &[Box<dyn Action + 'a>],
+        executed: &Consequence,
+    ) -> bool {
+        let assumed = match &self.assumed {
+            Some(assumed) => Some(assumed),
+            None => self.belief.most_likely(),
+        };
+        let applied = assumed
+            .and_then(|assumed| option_position(actions, assumed, executed))
+            .and_then(|option| {
+                self.belief
+                    .apply(actions, &executed.action, &executed.argument, option)
+            });
+        match applied {
+            Some((belief, _)) => self.belief = belief,
+            None => {
+                self.invalidate();
+                return true;
+            }
+        }
+
+        let likely = self.belief.most_likely();
+        let continued = match (&mut self.plan, &self.assumed) {
+            (Some((nodes, cost)), Some(assumed)) => match nodes.get(1) {
+                Some(Node::Consequence(consequence))
+                    if same_consequence(consequence, executed)
+                        && likely
+                            .filter(|likely| same_fields(likely, &consequence.result))
+                            .is_some() =>
+                {
+                    let step_cost = replay_step(assumed, actions, consequence).unwrap_or(0);
+                    *cost = cost.saturating_sub(step_cost);
+                    nodes.remove(0);
+                    Some(nodes[0].state().clone())
+                }
+                _ => None,
+            },
+            _ => None,
+        };
+        match continued {
+            Some(assumed) => self.assumed = Some(assumed),
+            None => self.invalidate(),
+        }
+        self.plan.is_none()
+    }
+
+    fn invalidate(&mut self) {
+        self.plan = None;
+        self.assumed = None;
+    }
+}
+
+// `State`'s equality goes through the distance between the states, which
+// can't tell close floats apart and ignores keys only one of them has
+fn same_fields(state: &State, other: &State) -> bool {
+    state.iter().eq(other.iter())
+}
+
+// Position of `consequence` among the options its action offers in `state`
+// with the same argument
+fn option_position<'a>(
+    actions: &[Box<dyn Action + 'a>],
+    state: &State,
+    consequence: &Consequence,
+) -> Option<usize> {
+    let action = actions
+        .iter()
+        .find(|action| action.key() == consequence.action)?;
+    action
+        .options(state)
+        .into_iter()
+        .filter(|(offered, _)| offered.argument == consequence.argument)
+        .position(|(offered, _)| same_consequence(&offered, consequence))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{at_least, Chop};
+
+    #[test]
+    fn worlds_with_other_fields_stay_apart() {
+        let belief = BeliefState::new()
+            .with_unknown("t", vec![(Field::from(2.0), 0.5), (Field::from(3.0), 0.5)]);
+        assert_eq!(belief.len(), 2);
+
+        let a = State::new().with_field("a", Field::from(1u64));
+        let belief = BeliefState::new()
+            .with_world(a.with_field("b", Field::from(2u64)), 0.5)
+            .with_world(a.clone(), 0.25)
+            .with_world(a, 0.25);
+        assert_eq!(belief.len(), 2);
+        assert_eq!(belief.worlds()[1].1, 0.5);
+    }
+
+    #[test]
+    fn options_with_the_same_argument_are_told_apart() {
+        let actions: Vec<Box<dyn Action>> = vec![Box::new(Chop)];
+        let belief = BeliefState::new()
+            .with_world(State::new().with_field("wood", Field::from(0u64)), 0.5)
+            .with_world(State::new().with_field("wood", Field::from(1u64)), 0.5);
+
+        let plan = plan_conditional(&belief, &actions, &at_least("wood", 8), 1).unwrap();
+        match &plan {
+            ConditionalPlan::Step { option, cost, .. } => {
+                assert_eq!(*option, 1);
+                assert_eq!(*cost, 3);
+            }
+            plan => panic!("expected a step, got {:?}", plan),
+        }
+
+        let mut planner = MostLikelyPlanner::new(belief, at_least("wood", 8));
+        let step = match &planner.plan(&actions).unwrap().0[1] {
+            Node::Consequence(consequence) => consequence.clone(),
+            node => panic!("expected a consequence, got {:?}", node),
+        };
+        assert!(!planner.execute(&actions, &step));
+        assert_eq!(
+            planner.belief().probability_of_goal(&at_least("wood", 8)),
+            1.0
+        );
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use crate::{
+    action::Action,
+    field::Field,
+    goal::Goal,
+    planner::{plan_with, Plan, PlanOptions},
+    state::State,
+};
+
+// Reads part of the game world into the state
+pub trait Sensor {
+    fn key(&self) -> String;
+    // Keys the sensor owns, other keys it senses are ignored
+    fn keys(&self) -> Vec<String>;
+    fn sense(&self) -> State;
+    // How long sensed values stay fresh, forever by default
+    fn max_age(&self) -> Option<Duration> {
+        None
+    }
+}
+
+// Aggregates sensors into one state. Values are refreshed on demand, by
+// `refresh_stale` on every tick of the game loop, or when a snapshot is
+// taken
+pub struct Blackboard<'s> {
+    sensors: Vec<Box<dyn Sensor + 's>>,
+    state: State,
+    updated: BTreeMap<String, Instant>,
+}
+
+impl<'s> Default for Blackboard<'s> {
+    fn default() -> Self {
+        Blackboard::new()
+    }
+}
+
+impl<'s> Blackboard<'s> {
+    pub fn new() -> Self {
+        Blackboard {
+            sensors: vec![],
+            state: State::new(),
+            updated: BTreeMap::new(),
+        }
+    }
+
+    pub fn add_sensor(&mut self, sensor: Box<dyn Sensor + 's>) {
+        self.sensors.push(sensor);
+    }
+
+    pub fn with_sensor(mut self, sensor: Box<dyn Sensor + 's>) -> Self {
+        self.add_sensor(sensor);
+        self
+    }
+
+    // Current values without refreshing anything
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    // Writes a value directly, it counts as fresh
+    pub fn set<S: AsRef<str>>(&mut self, key: S, value: Field) {
+        self.state.insert(key.as_ref(), value);
+        self.updated.insert(key.as_ref().to_owned(), Instant::now());
+    }
+
+    // Time since the key was last sensed or set, `None` if it never was
+    pub fn age<S: AsRef<str>>(&self, key: S) -> Option<Duration> {
+        self.updated
+            .get(key.as_ref())
+            .map(|updated| updated.elapsed())
+    }
+
+    // Keys owned by a sensor are stale until first sensed and once older
+    // than the sensor's maximum age
+    pub fn is_stale<S: AsRef<str>>(&self, key: S) -> bool {
+        match self.owner(key.as_ref()) {
+            Some(sensor) => self.sensor_key_stale(sensor, key.as_ref()),
+            None => false,
+        }
+    }
+
+    pub fn stale_keys(&self) -> Vec<String> {
+        self.sensors
+            .iter()
+            .flat_map(|sensor| {
+                sensor
+                    .keys()
+                    .into_iter()
+                    .filter(move |key| self.sensor_key_stale(&**sensor, key))
+            })
+            .collect()
+    }
+
+    pub fn refresh(&mut self) {
+        for index in 0..self.sensors.len() {
+            self.refresh_sensor(index);
+        }
+    }
+
+    // Refreshes the sensor owning the key, returns whether there is one
+    pub fn refresh_key<S: AsRef<str>>(&mut self, key: S) -> bool {
+        match self
+            .sensors
+            .iter()
+            .position(|sensor| sensor.keys().iter().any(|owned| owned == key.as_ref()))
+        {
+            Some(index) => {
+                self.refresh_sensor(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Refreshes every sensor with a stale key, returns how many there were
+    pub fn refresh_stale(&mut self) -> usize {
+        let stale: Vec<usize> = (0..self.sensors.len())
+            .filter(|&index| {
+                let sensor = &*self.sensors[index];
+                sensor
+                    .keys()
+                    .iter()
+                    .any(|key| self.sensor_key_stale(sensor, key))
+            })
+            .collect();
+        for &index in &stale {
+            self.refresh_sensor(index);
+        }
+        stale.len()
+    }
+
+    // Fresh state for the planner or the executor
+    pub fn snapshot(&mut self) -> State {
+        self.refresh_stale();
+        self.state.clone()
+    }
+
+    // Keys whose fresh value differs from `expected`, for example the state
+    // a plan expected to be in by now
+    pub fn changed_since(&mut self, expected: &State) -> Vec<String> {
+        let snapshot = self.snapshot();
+        let mut changed: Vec<String> = snapshot
+            .iter()
+            .filter(|(key, value)| expected.get(key).as_ref() != Some(*value))
+            .map(|(key, _)| key.clone())
+            .collect();
+        changed.extend(
+            expected
+                .iter()
+                .filter(|(key, _)| !snapshot.contains_key(key))
+                .map(|(key, _)| key.clone()),
+        );
+        changed
+    }
+
+    // Plans from a fresh snapshot
+    pub fn plan<'a>(
+        &mut self,
+        actions: &[Box<dyn Action + 'a>],
+        goal: &Goal,
+        options: &PlanOptions,
+    ) -> Option<Plan> {
+        let snapshot = self.snapshot();
+        plan_with(&snapshot, actions, goal, options)
+    }
+
+    fn owner(&self, key: &str) -> Option<&dyn Sensor> {
+        self.sensors
+            .iter()
+            .find(|sensor| sensor.keys().iter().any(|owned| owned == key))
+            .map(|sensor| &**sensor)
+    }
+
+    fn sensor_key_stale(&self, sensor: &dyn Sensor, key: &str) -> bool {
+        match (self.age(key), sensor.max_age()) {
+            (None, _) => true,
+            (Some(age), Some(max_age)) => age > max_age,
+            (Some(_), None) => false,
+        }
+    }
+
+    fn refresh_sensor(&mut self, index: usize) {
+        let sensor = &self.sensors[index];
+        debug!("----- Refreshing sensor {} -----", sensor.key());
+        let sensed = sensor.sense();
+        let now = Instant::now();
+        for key in sensor.keys() {
+            match sensed.get(&key) {
+                Some(value) => {
+                    self.state.insert(&key, value);
+                }
+                None => {
+                    self.state.remove(&key);
+                }
+            }
+            self.updated.insert(key, now);
+        }
+    }
+}
@@ -0,0 +1,346 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, HashMap};
+use std::hash::{Hash, Hasher};
+
+use crate::{
+    action::{Action, Consequence},
+    goal::Goal,
+    planner::{plan_with, prepare_checked, prepare_with, Node, Plan, PlanOptions},
+    state::State,
+};
+
+struct CacheEntry {
+    plan: Plan,
+    last_used: u64,
+}
+
+pub struct PlanCache {
+    capacity: usize,
+    version: u64,
+    relevant_keys: Option<BTreeSet<String>>,
+    entries: HashMap<u64, CacheEntry>,
+    clock: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl PlanCache {
+    pub fn new(capacity: usize) -> Self {
+        PlanCache {
+            capacity,
+            version: 0,
+            relevant_keys: None,
+            entries: HashMap::new(),
+            clock: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    // Only these start state keys take part in the cache key, by default
+    // all of them do
+    pub fn with_relevant_keys<I, S>(mut self, keys: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        self.relevant_keys = Some(
+            keys.into_iter()
+                .map(|key| key.as_ref().to_owned())
+                .collect(),
+        );
+        self
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    // Has to be called whenever the behavior of the actions changes
+    pub fn invalidate(&mut self) {
+        self.version += 1;
+        self.entries.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses
+    }
+
+    pub fn plan<'a>(
+        &mut self,
+        start: &State,
+        actions: &[Box<dyn Action + 'a>],
+        goal: &Goal,
+        options: &PlanOptions,
+    ) -> Option<Plan> {
+        if let Some(plan) = self.get_with(start, actions, goal, options) {
+            return Some(plan);
+        }
+        let plan = plan_with(start, actions, goal, options)?;
+        self.insert_with(start, actions, goal, options, plan.clone());
+        Some(plan)
+    }
+
+    pub fn get<'a>(
+        &mut self,
+        start: &State,
+        actions: &[Box<dyn Action + 'a>],
+        goal: &Goal,
+    ) -> Option<Plan> {
+        self.get_with(start, actions, goal, &PlanOptions::new())
+    }
+
+    // Cached plans are replayed from `start` before they are returned, so
+    // the steps carry this start state's own results and costs. Plans are
+    // only shared between equal options, and a replayed plan has to keep
+    // the options' constraints and the goal's deadlines
+    pub fn get_with<'a>(
+        &mut self,
+        start: &State,
+        actions: &[Box<dyn Action + 'a>],
+        goal: &Goal,
+        options: &PlanOptions,
+    ) -> Option<Plan> {
+        let start = prepare_checked(start, actions, options)?;
+        let key = self.key(&start, actions, goal, options);
+        self.clock += 1;
+
+        let cached = match self.entries.get_mut(&key) {
+            Some(entry) => {
+                entry.last_used = self.clock;
+                entry.plan.clone()
+            }
+            None => {
+                self.misses += 1;
+                return None;
+            }
+        };
+        match self.rebase(&cached, start, actions, goal, options) {
+            Some(plan) => {
+                self.hits += 1;
+                Some(plan)
+            }
+            None => {
+                debug!("----- Cached plan is stale -----");
+                self.entries.remove(&key);
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    pub fn insert<'a>(
+        &mut self,
+        start: &State,
+        actions: &[Box<dyn Action + 'a>],
+        goal: &Goal,
+        plan: Plan,
+    ) {
+        self.insert_with(start, actions, goal, &PlanOptions::new(), plan)
+    }
+
+    pub fn insert_with<'a>(
+        &mut self,
+        start: &State,
+        actions: &[Box<dyn Action + 'a>],
+        goal: &Goal,
+        options: &PlanOptions,
+        plan: Plan,
+    ) {
+        if self.capacity == 0 {
+            return;
+        }
+        let start = prepare_with(start, actions, options);
+        let key = self.key(&start, actions, goal, options);
+        if !self.entries.contains_key(&key) && self.entries.len() >= self.capacity {
+            self.evict();
+        }
+        self.clock += 1;
+        self.entries.insert(
+            key,
+            CacheEntry {
+                plan,
+                last_used: self.clock,
+            },
+        );
+    }
+
+    fn evict(&mut self) {
+        let oldest = self
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(key, _)| *key);
+        if let Some(oldest) = oldest {
+            self.entries.remove(&oldest);
+        }
+    }
+
+    fn key<'a>(
+        &self,
+        start: &State,
+        actions: &[Box<dyn Action + 'a>],
+        goal: &Goal,
+        options: &PlanOptions,
+    ) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.version.hash(&mut hasher);
+        for action in actions {
+            action.key().hash(&mut hasher);
+        }
+        for (key, requirement) in goal.requirements() {
+            key.hash(&mut hasher);
+            requirement.description().hash(&mut hasher);
+            goal.requirement_deadline(key).hash(&mut hasher);
+        }
+        goal.deadline().hash(&mut hasher);
+        // Budgets only decide whether a plan is found, not which one
+        options.seed().hash(&mut hasher);
+        for constraint in options.constraints().constraints() {
+            constraint.name().hash(&mut hasher);
+            constraint.key().hash(&mut hasher);
+            constraint.requirement().description().hash(&mut hasher);
+        }
+        if let Some(schema) = options.schema() {
+            for (key, schema) in schema.keys() {
+                key.hash(&mut hasher);
+                schema.field_type().hash(&mut hasher);
+                schema.default_value().hash(&mut hasher);
+                schema.min().map(f64::to_bits).hash(&mut hasher);
+                schema.max().map(f64::to_bits).hash(&mut hasher);
+            }
+        }
+        for (key, value) in start.iter() {
+            if self.is_relevant(key) {
+                key.hash(&mut hasher);
+                value.hash(&mut hasher);
+            }
+        }
+        hasher.finish()
+    }
+
+    fn is_relevant(&self, key: &str) -> bool {
+        match &self.relevant_keys {
+            Some(keys) => keys.contains(key),
+            None => true,
+        }
+    }
+
+    fn rebase<'a>(
+        &self,
+        plan: &Plan,
+        start: State,
+        actions: &[Box<dyn Action + 'a>],
+        goal: &Goal,
+        options: &PlanOptions,
+    ) -> Option<Plan> {
+        if !goal.can_meet_deadlines(&start, 0) {
+            return None;
+        }
+        let mut state = start.clone();
+        let mut nodes = vec![Node::State(start)];
+        let mut cost = 0;
+        for node in plan.0.iter().skip(1) {
+            let recorded = match node {
+                Node::Consequence(consequence) => consequence,
+                Node::State(_) => return None,
+            };
+            let action = actions
+                .iter()
+                .find(|action| action.key() == recorded.action)?;
+            let (consequence, step_cost) = action
+                .options(&state)
+                .into_iter()
+                .filter(|(offered, _)| self.matches(offered, recorded))
+                .min_by_key(|(_, cost)| *cost)?;
+            cost += step_cost;
+            state = consequence.result.clone();
+            if options.check(&state).is_some() || !goal.can_meet_deadlines(&state, cost) {
+                return None;
+            }
+            nodes.push(Node::Consequence(consequence));
+        }
+        if state.distance_to_goal(goal) != 0 {
+            return None;
+        }
+        Some((nodes, cost))
+    }
+
+    fn matches(&self, offered: &Consequence, recorded: &Consequence) -> bool {
+        offered.action == recorded.action
+            && offered.argument == recorded.argument
+            && recorded
+                .result
+                .iter()
+                .filter(|(key, _)| self.is_relevant(key))
+                .all(|(key, value)| offered.result.get(key).as_ref() == Some(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        constraint::ConstraintSet,
+        field::Field,
+        goal::Deadline,
+        requirement::CompareRequirement,
+        test_support::{equals, Add},
+    };
+
+    fn start(clock: u64) -> State {
+        State::new()
+            .with_field("x", Field::from(0u64))
+            .with_field("clock", Field::from(clock))
+    }
+
+    #[test]
+    fn plans_are_not_shared_between_constraints() {
+        let actions: Vec<Box<dyn Action>> = vec![Box::new(Add(1))];
+        let mut cache = PlanCache::new(4);
+        assert!(cache
+            .plan(&start(0), &actions, &equals("x", 2), &PlanOptions::new())
+            .is_some());
+        assert!(cache.get(&start(0), &actions, &equals("x", 2)).is_some());
+
+        let options = PlanOptions::new().with_constraints(ConstraintSet::new().with_constraint(
+            "x stays low",
+            "x",
+            Box::new(CompareRequirement::LessThanEquals(Field::from(1u64))),
+        ));
+        assert!(cache
+            .get_with(&start(0), &actions, &equals("x", 2), &options)
+            .is_none());
+        assert!(cache
+            .plan(&start(0), &actions, &equals("x", 2), &options)
+            .is_none());
+    }
+
+    #[test]
+    fn rebased_plans_keep_deadlines() {
+        let actions: Vec<Box<dyn Action>> = vec![Box::new(Add(1))];
+        let goal = equals("x", 2).with_deadline(Deadline::time("clock", 3));
+        let mut cache = PlanCache::new(4).with_relevant_keys(vec!["x"]);
+        let options = PlanOptions::new();
+        assert!(cache.plan(&start(0), &actions, &goal, &options).is_some());
+        assert!(cache
+            .get_with(&start(1), &actions, &goal, &options)
+            .is_some());
+        // Same cache key, but the clock runs out on the way
+        assert!(cache
+            .get_with(&start(2), &actions, &goal, &options)
+            .is_none());
+    }
+}
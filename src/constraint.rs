@@ -0,0 +1,177 @@
+use std::sync::Arc;
+
+use crate::{field::Field, requirement::SyncRequirement, state::State};
+
+// A requirement every state of a plan has to meet, not just the last one.
+// Constraints are shared with the threads of the parallel planners, so
+// their requirements have to be thread-safe
+#[derive(Debug)]
+pub struct Constraint {
+    name: String,
+    key: String,
+    requirement: SyncRequirement,
+}
+
+impl Constraint {
+    pub fn new<N: AsRef<str>, K: AsRef<str>>(
+        name: N,
+        key: K,
+        requirement: SyncRequirement,
+    ) -> Self {
+        Constraint {
+            name: name.as_ref().to_owned(),
+            key: key.as_ref().to_owned(),
+            requirement,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn requirement(&self) -> &SyncRequirement {
+        &self.requirement
+    }
+
+    // States without the key don't violate the constraint, a value of
+    // another type than the requirement compares does
+    pub fn check(&self, state: &State) -> Option<Violation> {
+        let value = state.get(&self.key)?;
+        let violated = match self.requirement.field_type() {
+            Some(expected) if value.field_type() != expected => true,
+            _ => self.requirement.distance_from(&value) > 0,
+        };
+        if violated {
+            Some(Violation {
+                constraint: self.name.clone(),
+                key: self.key.clone(),
+                value,
+                requirement: self.requirement.description(),
+            })
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Violation {
+    pub constraint: String,
+    pub key: String,
+    pub value: Field,
+    // Description of the violated requirement
+    pub requirement: String,
+}
+
+impl Violation {
+    pub fn description(&self) -> String {
+        format!(
+            "{}: {} is {:?}, expected {}",
+            self.constraint, self.key, self.value, self.requirement
+        )
+    }
+}
+
+// Constraints are shared, so cloning a set or the options holding it is cheap
+#[derive(Clone, Debug, Default)]
+pub struct ConstraintSet {
+    constraints: Vec<Arc<Constraint>>,
+}
+
+impl ConstraintSet {
+    pub fn new() -> Self {
+        ConstraintSet::default()
+    }
+
+    pub fn add(&mut self, constraint: Constraint) {
+        self.constraints.push(Arc::new(constraint));
+    }
+
+    pub fn with(mut self, constraint: Constraint) -> Self {
+        self.add(constraint);
+        self
+    }
+
+    pub fn with_constraint<N: AsRef<str>, K: AsRef<str>>(
+        self,
+        name: N,
+        key: K,
+        requirement: SyncRequirement,
+    ) -> Self {
+        self.with(Constraint::new(name, key, requirement))
+    }
+
+    // Both sets, this one's constraints first
+    pub fn merged(mut self, other: &ConstraintSet) -> Self {
+        self.constraints.extend(other.constraints.iter().cloned());
+        self
+    }
+
+    pub fn constraints(&self) -> impl Iterator<Item = &Constraint> {
+        self.constraints.iter().map(|constraint| &**constraint)
+    }
+
+    pub fn len(&self) -> usize {
+        self.constraints.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.constraints.is_empty()
+    }
+
+    // The first constraint the state violates, in the order they were added
+    pub fn check(&self, state: &State) -> Option<Violation> {
+        self.constraints
+            .iter()
+            .find_map(|constraint| constraint.check(state))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        action::Action,
+        goal::Goal,
+        planner::{plan_with, PlanOptions},
+        requirement::CompareRequirement,
+        test_support::Heat,
+    };
+
+    #[test]
+    fn floats_over_the_bound_violate_it() {
+        let constraints = ConstraintSet::new().with_constraint(
+            "cool",
+            "t",
+            Box::new(CompareRequirement::LessThanEquals(Field::from(2.0))),
+        );
+        let at = |t: f64| State::new().with_field("t", Field::from(t));
+        assert!(constraints.check(&at(2.0)).is_none());
+        assert!(constraints.check(&at(2.5)).is_some());
+        assert!(constraints.check(&at(5.0)).is_some());
+
+        let actions: Vec<Box<dyn Action>> = vec![Box::new(Heat)];
+        let goal = Goal::new().with_req(
+            "t",
+            Box::new(CompareRequirement::MoreThanEquals(Field::from(2.0))),
+        );
+        let start = at(0.0);
+        let options = PlanOptions::new();
+        let (nodes, _) = plan_with(&start, &actions, &goal, &options).unwrap();
+        assert_eq!(
+            nodes.last().unwrap().state().get("t"),
+            Some(Field::from(5.0))
+        );
+
+        let options = options.with_constraints(constraints.clone());
+        let (nodes, cost) = plan_with(&start, &actions, &goal, &options).unwrap();
+        assert_eq!(cost, 2);
+        assert!(nodes
+            .iter()
+            .all(|node| constraints.check(node.state()).is_none()));
+    }
+}
@@ -0,0 +1,157 @@
+use std::convert::TryFrom;
+
+use serde_json::Value;
+
+use crate::{
+    action::Action,
+    field::Field,
+    goal::Goal,
+    key::FieldValue,
+    planner::{plan_with, Plan, PlanOptions},
+    state::State,
+};
+
+#[cfg(feature = "derive")]
+pub use soap_derive::GoapState;
+
+// Values convertible to and from a state, usually through
+// `#[derive(GoapState)]`. Primitives are a single field at `prefix`, structs
+// write each of their fields at `prefix.field`
+pub trait GoapState: Sized {
+    fn write_state(&self, prefix: &str, state: &mut State);
+    // `None` when a key is missing or holds a value that doesn't fit
+    fn read_state(state: &State, prefix: &str) -> Option<Self>;
+
+    fn to_state(&self) -> State {
+        let mut state = State::new();
+        self.write_state("", &mut state);
+        state
+    }
+
+    fn from_state(state: &State) -> Option<Self> {
+        Self::read_state(state, "")
+    }
+}
+
+// Key of a struct field, nested fields are joined with dots
+pub fn join_key(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{}.{}", prefix, name)
+    }
+}
+
+macro_rules! impl_field_state {
+    ($($ty:ty),*) => {
+        $(
+            impl GoapState for $ty {
+                fn write_state(&self, prefix: &str, state: &mut State) {
+                    state.insert(prefix, self.clone().into_field());
+                }
+
+                fn read_state(state: &State, prefix: &str) -> Option<Self> {
+                    state.get(prefix).and_then(|field| <$ty>::from_field(&field))
+                }
+            }
+        )*
+    };
+}
+
+impl_field_state!(bool, u64, i64, f64, String, Value);
+
+// Narrower numbers are stored widened, reading a value out of their range
+// fails instead of truncating
+macro_rules! impl_widened_state {
+    ($wide:ty => $($ty:ty),*) => {
+        $(
+            impl GoapState for $ty {
+                fn write_state(&self, prefix: &str, state: &mut State) {
+                    state.insert(prefix, (*self as $wide).into_field());
+                }
+
+                fn read_state(state: &State, prefix: &str) -> Option<Self> {
+                    let field = state.get(prefix)?;
+                    <$ty>::try_from(<$wide>::from_field(&field)?).ok()
+                }
+            }
+        )*
+    };
+}
+
+impl_widened_state!(u64 => u8, u16, u32, usize);
+impl_widened_state!(i64 => i8, i16, i32, isize);
+
+impl GoapState for f32 {
+    fn write_state(&self, prefix: &str, state: &mut State) {
+        state.insert(prefix, Field::from(*self));
+    }
+
+    fn read_state(state: &State, prefix: &str) -> Option<Self> {
+        state.get_as_f64(prefix).map(|value| value as f32)
+    }
+}
+
+// `None` leaves the keys out of the state
+impl<T: GoapState> GoapState for Option<T> {
+    fn write_state(&self, prefix: &str, state: &mut State) {
+        if let Some(value) = self {
+            value.write_state(prefix, state);
+        }
+    }
+
+    fn read_state(state: &State, prefix: &str) -> Option<Self> {
+        Some(T::read_state(state, prefix))
+    }
+}
+
+// Plans from a value and reads the final state of the plan back into one
+pub fn plan_value<'a, T: GoapState>(
+    start: &T,
+    actions: &[Box<dyn Action + 'a>],
+    goal: &Goal,
+    options: &PlanOptions,
+) -> Option<(Plan, T)> {
+    let plan = plan_with(&start.to_state(), actions, goal, options)?;
+    let last = plan.0.last()?;
+    let value = T::from_state(last.state())?;
+    Some((plan, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn widened_numbers_out_of_range_read_as_none() {
+        let state = 300u32.to_state();
+        assert_eq!(state.get(""), Some(Field::from(300u64)));
+        assert_eq!(u32::from_state(&state), Some(300));
+        assert_eq!(u8::from_state(&state), None);
+
+        let state = (-200i32).to_state();
+        assert_eq!(i16::from_state(&state), Some(-200));
+        assert_eq!(i8::from_state(&state), None);
+        assert_eq!(u32::from_state(&state), None);
+    }
+
+    #[test]
+    fn options_leave_their_keys_out() {
+        let mut state = State::new();
+        Some(3u8).write_state("wood", &mut state);
+        None::<u8>.write_state("coins", &mut state);
+        assert!(!state.contains_key("coins"));
+        assert_eq!(Option::<u8>::read_state(&state, "wood"), Some(Some(3)));
+        assert_eq!(Option::<u8>::read_state(&state, "coins"), Some(None));
+        // A value that doesn't fit reads as missing too
+        let state = state.with_field("coins", Field::from(true));
+        assert_eq!(Option::<u8>::read_state(&state, "coins"), Some(None));
+    }
+
+    #[test]
+    fn nested_keys_are_joined_with_dots() {
+        assert_eq!(join_key("", "wood"), "wood");
+        assert_eq!(join_key("tools", "axe"), "tools.axe");
+        assert_eq!(join_key("camp.tools", "axe"), "camp.tools.axe");
+    }
+}
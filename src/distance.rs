@@ -21,7 +21,8 @@ pub fn distance_u64(this: u64, other: u64) -> u64 {
 
 #[inline]
 pub fn distance_f64(this: f64, other: f64) -> u64 {
-    (this.max(other) - this.min(other) * 100.0).round() as u64
+    // Any difference counts, so only equal values are 0 apart
+    ((this.max(other) - this.min(other)) * 100.0).ceil() as u64
 }
 
 impl Field {
@@ -0,0 +1,300 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
+
+use serde_json::Value;
+
+use crate::{
+    action::{step_label, Action},
+    constraint::ConstraintSet,
+    field::FieldType,
+    goal::Goal,
+    planner::{prepare_with, Node, Plan, PlanOptions},
+    requirement::BoxedRequirement,
+    schema::SchemaError,
+    state::{State, StateKey},
+};
+
+#[derive(Clone, Debug)]
+pub struct RequirementProgress {
+    pub key: String,
+    pub requirement: String,
+    pub before: u64,
+    pub after: u64,
+}
+
+impl RequirementProgress {
+    // Positive when the step moved the state closer to the requirement
+    pub fn advanced(&self) -> i64 {
+        self.before as i64 - self.after as i64
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct StepExplanation {
+    pub step: usize,
+    pub action: String,
+    pub argument: Option<Value>,
+    pub progress: Vec<RequirementProgress>,
+}
+
+impl StepExplanation {
+    pub fn advanced(&self) -> impl Iterator<Item = &RequirementProgress> {
+        self.progress
+            .iter()
+            .filter(|progress| progress.advanced() > 0)
+    }
+
+    pub fn description(&self) -> String {
+        let action = step_label(&self.action, self.argument.as_ref());
+        if self.progress.is_empty() {
+            return format!("{}: no goal requirement changed", action);
+        }
+        let changes: Vec<String> = self
+            .progress
+            .iter()
+            .map(|progress| {
+                format!(
+                    "{} {} by {} ({} -> {})",
+                    progress.key,
+                    if progress.advanced() > 0 {
+                        "advanced"
+                    } else {
+                        "regressed"
+                    },
+                    progress.advanced().abs(),
+                    progress.before,
+                    progress.after
+                )
+            })
+            .collect();
+        format!("{}: {}", action, changes.join(", "))
+    }
+}
+
+pub fn explain(plan: &Plan, goal: &Goal) -> Vec<StepExplanation> {
+    let (nodes, _) = plan;
+    nodes
+        .windows(2)
+        .enumerate()
+        .filter_map(|(index, pair)| {
+            let consequence = match &pair[1] {
+                Node::Consequence(consequence) => consequence,
+                Node::State(_) => return None,
+            };
+            let before = pair[0].state();
+            let after = &consequence.result;
+            let progress = goal
+                .requirements()
+                .iter()
+                .map(|(key, requirement)| RequirementProgress {
+                    key: key.clone(),
+                    requirement: requirement.description(),
+                    before: before.distance_to_requirement(key, requirement),
+                    after: after.distance_to_requirement(key, requirement),
+                })
+                .filter(|progress| progress.before != progress.after)
+                .collect();
+            Some(StepExplanation {
+                step: index + 1,
+                action: consequence.action.clone(),
+                argument: consequence.argument.clone(),
+                progress,
+            })
+        })
+        .collect()
+}
+
+#[derive(Clone, Debug)]
+pub struct TypeMismatch {
+    pub key: String,
+    pub expected: FieldType,
+    pub found: Vec<FieldType>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Diagnosis {
+    // Unsatisfied goal keys that no action ever writes
+    pub unwritten_keys: Vec<String>,
+    pub type_mismatches: Vec<TypeMismatch>,
+    // Actions without any option in the explored states
+    pub inapplicable_actions: Vec<String>,
+    pub explored_states: usize,
+    // Whether every reachable state was explored within the limit
+    pub exhausted: bool,
+    // Explored state closest to the goal, ignoring mismatched requirements
+    pub closest: Option<(State, u64)>,
+    // Consequences left unexplored by each constraint, keyed by its name
+    pub constraint_violations: BTreeMap<String, usize>,
+    // Why the start or the goal doesn't fit the schema of the options,
+    // which keeps them from being planned for
+    pub schema_errors: Vec<SchemaError>,
+}
+
+pub fn diagnose<'a>(
+    start: &State,
+    actions: &[Box<dyn Action + 'a>],
+    goal: &Goal,
+    max_states: usize,
+) -> Diagnosis {
+    diagnose_with_constraints(start, actions, goal, &ConstraintSet::new(), max_states)
+}
+
+// Same as `diagnose`, but only explores states meeting the constraints
+pub fn diagnose_with_constraints<'a>(
+    start: &State,
+    actions: &[Box<dyn Action + 'a>],
+    goal: &Goal,
+    constraints: &ConstraintSet,
+    max_states: usize,
+) -> Diagnosis {
+    let options = PlanOptions::new().with_constraints(constraints.clone());
+    diagnose_with(start, actions, goal, &options, max_states)
+}
+
+// Explores from the start prepared like `plan_with` would with the options,
+// only states meeting their constraints and schema bounds
+pub fn diagnose_with<'a>(
+    start: &State,
+    actions: &[Box<dyn Action + 'a>],
+    goal: &Goal,
+    options: &PlanOptions,
+    max_states: usize,
+) -> Diagnosis {
+    let schema_errors = match options.schema() {
+        Some(schema) => {
+            let mut errors = schema.prepare(start).err().unwrap_or_default();
+            errors.extend(schema.validate_goal(goal));
+            errors
+        }
+        None => vec![],
+    };
+    let start = prepare_with(start, actions, options);
+
+    let mut written_keys: HashSet<String> = HashSet::new();
+    let mut key_types: BTreeMap<String, BTreeSet<FieldType>> = BTreeMap::new();
+    let mut applicable: HashSet<String> = HashSet::new();
+    let mut visited: HashSet<StateKey> = HashSet::new();
+    let mut queue = VecDeque::new();
+    record_types(&start, &mut key_types);
+    visited.insert(StateKey::new(&start));
+    queue.push_back(start.clone());
+
+    let mut explored_states = 0;
+    let mut closest: Option<(State, u64)> = None;
+    let mut constraint_violations: BTreeMap<String, usize> = BTreeMap::new();
+    while let Some(state) = queue.pop_front() {
+        if explored_states >= max_states {
+            queue.push_front(state);
+            break;
+        }
+        explored_states += 1;
+        let distance = comparable_distance(&state, goal);
+        let closer = match &closest {
+            Some((_, best)) => distance < *best,
+            None => true,
+        };
+        if closer {
+            closest = Some((state.clone(), distance));
+        }
+        for action in actions {
+            for (consequence, _) in action.options(&state) {
+                if let Some(violation) = options.check(&consequence.result) {
+                    *constraint_violations
+                        .entry(violation.constraint)
+                        .or_insert(0) += 1;
+                    continue;
+                }
+                applicable.insert(action.key());
+                for (key, value) in consequence.result.iter() {
+                    if state.get(key).as_ref() != Some(value) {
+                        written_keys.insert(key.clone());
+                    }
+                }
+                record_types(&consequence.result, &mut key_types);
+                if visited.insert(StateKey::new(&consequence.result)) {
+                    queue.push_back(consequence.result);
+                }
+            }
+        }
+    }
+
+    let mut unwritten_keys = vec![];
+    let mut type_mismatches = vec![];
+    for (key, requirement) in goal.requirements() {
+        let found: Vec<FieldType> = key_types
+            .get(key)
+            .map(|types| types.iter().cloned().collect())
+            .unwrap_or_default();
+        let mismatch = match requirement.field_type() {
+            Some(expected) if !found.is_empty() && !found.contains(&expected) => {
+                type_mismatches.push(TypeMismatch {
+                    key: key.clone(),
+                    expected,
+                    found,
+                });
+                true
+            }
+            _ => false,
+        };
+        let satisfied = !mismatch && requirement_distance(&start, key, requirement) == Some(0);
+        if !written_keys.contains(key) && !satisfied {
+            unwritten_keys.push(key.clone());
+        }
+    }
+
+    let inapplicable_actions = actions
+        .iter()
+        .map(|action| action.key())
+        .filter(|key| !applicable.contains(key))
+        .collect();
+
+    Diagnosis {
+        unwritten_keys,
+        type_mismatches,
+        inapplicable_actions,
+        explored_states,
+        exhausted: queue.is_empty(),
+        closest,
+        constraint_violations,
+        schema_errors,
+    }
+}
+
+fn record_types(state: &State, key_types: &mut BTreeMap<String, BTreeSet<FieldType>>) {
+    for (key, value) in state.iter() {
+        key_types
+            .entry(key.clone())
+            .or_default()
+            .insert(value.field_type());
+    }
+}
+
+// Requirements panic on fields of another type, so those are skipped
+fn comparable_distance(state: &State, goal: &Goal) -> u64 {
+    goal.requirements()
+        .iter()
+        .map(|(key, requirement)| requirement_distance(state, key, requirement).unwrap_or(0))
+        .sum()
+}
+
+fn requirement_distance(state: &State, key: &str, requirement: &BoxedRequirement) -> Option<u64> {
+    match (state.get(key), requirement.field_type()) {
+        (Some(field), Some(expected)) if field.field_type() != expected => None,
+        _ => Some(state.distance_to_requirement(key, requirement)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{equals, Walk};
+
+    #[test]
+    fn states_holding_json_values_are_told_apart() {
+        let actions: Vec<Box<dyn Action>> = vec![Box::new(Walk(5))];
+        let diagnosis = diagnose(&State::new(), &actions, &equals("found", 1), 100);
+        assert!(diagnosis.exhausted);
+        // The start without a position counts as the first cell
+        assert_eq!(diagnosis.explored_states, 25);
+        assert_eq!(diagnosis.unwritten_keys, vec!["found".to_owned()]);
+    }
+}
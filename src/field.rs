@@ -24,6 +24,16 @@ impl Distance {
     }
 }
 
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum FieldType {
+    Value,
+    Bool,
+    String,
+    U64,
+    I64,
+    F64,
+}
+
 #[derive(Clone)]
 pub enum Field {
     Value(Value),
@@ -35,6 +45,26 @@ pub enum Field {
 }
 
 impl Field {
+    pub fn field_type(&self) -> FieldType {
+        match self {
+            Field::Value(_) => FieldType::Value,
+            Field::Bool(_) => FieldType::Bool,
+            Field::String(_) => FieldType::String,
+            Field::U64(_) => FieldType::U64,
+            Field::I64(_) => FieldType::I64,
+            Field::F64(_) => FieldType::F64,
+        }
+    }
+    pub fn to_value(&self) -> Value {
+        match self {
+            Field::Value(val) => val.clone(),
+            Field::Bool(val) => Value::from(*val),
+            Field::String(val) => Value::from(val.clone()),
+            Field::U64(val) => Value::from(*val),
+            Field::I64(val) => Value::from(*val),
+            Field::F64(val) => Value::from(*val),
+        }
+    }
     pub fn as_value(&self) -> Option<Value> {
         match self {
             Field::Value(val) => Some(val.clone()),
@@ -213,5 +243,30 @@ pub fn hash_value<H: Hasher>(value: &Value, state: &mut H) {
         }
     }
 
+    // Null
+    if let Value::Null = value {
+        0u8.hash(state);
+        return;
+    }
+
+    // Array
+    if let Value::Array(this) = value {
+        this.len().hash(state);
+        for item in this {
+            hash_value(item, state);
+        }
+        return;
+    }
+
+    // Object
+    if let Value::Object(this) = value {
+        this.len().hash(state);
+        for (key, item) in this {
+            key.hash(state);
+            hash_value(item, state);
+        }
+        return;
+    }
+
     panic!("Hashing this type ({:?}) is not supported", value)
 }
@@ -1,19 +1,61 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::sync::Arc;
 
-use crate::requirement::BoxedRequirement;
+use crate::{
+    requirement::{BoxedRequirement, Requirement, SyncRequirement},
+    state::State,
+};
+
+#[derive(Clone, Debug, Hash, PartialEq)]
+pub enum Deadline {
+    // Accumulated cost of the plan
+    Cost(u64),
+    // Value of a u64 field the actions advance, such as a clock
+    Time { key: String, until: u64 },
+}
+
+impl Deadline {
+    pub fn cost(until: u64) -> Self {
+        Deadline::Cost(until)
+    }
+
+    pub fn time<S: AsRef<str>>(key: S, until: u64) -> Self {
+        Deadline::Time {
+            key: key.as_ref().to_owned(),
+            until,
+        }
+    }
+
+    // Cost or time left, `None` once the deadline passed. A missing time
+    // field counts as 0
+    pub fn remaining(&self, state: &State, cost: u64) -> Option<u64> {
+        match self {
+            Deadline::Cost(until) => until.checked_sub(cost),
+            Deadline::Time { key, until } => until.checked_sub(state.get_as_u64(key).unwrap_or(0)),
+        }
+    }
+}
+
+pub type CostToGo = Box<dyn Fn(&State) -> u64 + Send + Sync>;
 
 pub struct Goal {
-    requirements: HashMap<String, BoxedRequirement>,
+    requirements: BTreeMap<String, BoxedRequirement>,
+    deadline: Option<Deadline>,
+    requirement_deadlines: BTreeMap<String, Deadline>,
+    cost_to_go: Option<CostToGo>,
 }
 
 impl Goal {
     pub fn new() -> Self {
         Goal {
-            requirements: HashMap::new(),
+            requirements: BTreeMap::new(),
+            deadline: None,
+            requirement_deadlines: BTreeMap::new(),
+            cost_to_go: None,
         }
     }
 
-    pub fn requirements(&self) -> &HashMap<String, BoxedRequirement> {
+    pub fn requirements(&self) -> &BTreeMap<String, BoxedRequirement> {
         &self.requirements
     }
 
@@ -39,6 +81,66 @@ impl Goal {
         self.insert(key, value);
         self
     }
+
+    pub fn deadline(&self) -> Option<&Deadline> {
+        self.deadline.as_ref()
+    }
+
+    pub fn requirement_deadline<S: AsRef<str>>(&self, key: S) -> Option<&Deadline> {
+        self.requirement_deadlines.get(key.as_ref())
+    }
+
+    // The whole goal has to be reached before the deadline
+    pub fn with_deadline(mut self, deadline: Deadline) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    // The requirement has to hold once the deadline passed, in every state
+    // up to the end of the plan
+    pub fn with_req_by<S: AsRef<str>>(
+        mut self,
+        key: S,
+        value: BoxedRequirement,
+        deadline: Deadline,
+    ) -> Self {
+        self.requirement_deadlines
+            .insert(key.as_ref().to_owned(), deadline);
+        self.with_req(key, value)
+    }
+
+    // Lower bound on the cost or time still needed to reach the goal, in the
+    // unit of the deadline. Branches that can't make the deadline even then
+    // are pruned, so it must never overestimate. Without it only branches
+    // past the deadline are pruned
+    pub fn with_cost_to_go(mut self, cost_to_go: CostToGo) -> Self {
+        self.cost_to_go = Some(cost_to_go);
+        self
+    }
+
+    // Whether a state reached at `cost` can still meet every deadline
+    pub fn can_meet_deadlines(&self, state: &State, cost: u64) -> bool {
+        for (key, deadline) in &self.requirement_deadlines {
+            let met = match self.requirements.get(key) {
+                Some(requirement) => state.distance_to_requirement(key, requirement) == 0,
+                None => true,
+            };
+            if !met && deadline.remaining(state, cost).is_none() {
+                return false;
+            }
+        }
+        match &self.deadline {
+            Some(deadline) => {
+                let needed = if state.distance_to_goal(self) == 0 {
+                    0
+                } else {
+                    self.cost_to_go.as_ref().map_or(0, |bound| bound(state))
+                };
+                matches!(deadline.remaining(state, cost), Some(remaining) if remaining >= needed)
+            }
+            None => true,
+        }
+    }
 }
 
 impl std::fmt::Debug for Goal {
@@ -46,3 +148,68 @@ impl std::fmt::Debug for Goal {
         self.requirements.fmt(f)
     }
 }
+
+// Thread-safe variant of `Goal` for planning on other threads, every thread
+// plans towards its own `Goal` sharing the requirements
+#[derive(Clone, Default)]
+pub struct SyncGoal {
+    requirements: BTreeMap<String, Arc<dyn Requirement + Send + Sync>>,
+    deadline: Option<Deadline>,
+    requirement_deadlines: BTreeMap<String, Deadline>,
+    cost_to_go: Option<SharedCostToGo>,
+}
+
+type SharedCostToGo = Arc<dyn Fn(&State) -> u64 + Send + Sync>;
+
+impl SyncGoal {
+    pub fn new() -> Self {
+        SyncGoal::default()
+    }
+
+    pub fn with_req<S: AsRef<str>>(mut self, key: S, value: SyncRequirement) -> Self {
+        self.requirements
+            .insert(key.as_ref().to_owned(), Arc::from(value));
+        self
+    }
+
+    pub fn with_req_by<S: AsRef<str>>(
+        mut self,
+        key: S,
+        value: SyncRequirement,
+        deadline: Deadline,
+    ) -> Self {
+        self.requirement_deadlines
+            .insert(key.as_ref().to_owned(), deadline);
+        self.with_req(key, value)
+    }
+
+    pub fn with_deadline(mut self, deadline: Deadline) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    pub fn with_cost_to_go(mut self, cost_to_go: CostToGo) -> Self {
+        self.cost_to_go = Some(Arc::from(cost_to_go));
+        self
+    }
+
+    pub fn goal(&self) -> Goal {
+        let mut goal = Goal::new();
+        for (key, requirement) in &self.requirements {
+            goal.insert(key, Box::new(requirement.clone()));
+        }
+        goal.deadline = self.deadline.clone();
+        goal.requirement_deadlines = self.requirement_deadlines.clone();
+        goal.cost_to_go = self
+            .cost_to_go
+            .clone()
+            .map(|cost_to_go| Box::new(move |state: &State| cost_to_go(state)) as CostToGo);
+        goal
+    }
+}
+
+impl std::fmt::Debug for SyncGoal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.goal().fmt(f)
+    }
+}
@@ -0,0 +1,338 @@
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+
+use crate::{
+    action::{step_label, Action},
+    goal::Goal,
+    planner::{plan_with, prepare_checked, Node, Plan, PlanOptions},
+    requirement::BoxedRequirement,
+    state::State,
+};
+
+pub enum Task {
+    // One of the consequences offered by the action with this key,
+    // optionally only those with the given argument
+    Primitive {
+        action: String,
+        argument: Option<Value>,
+    },
+    // Decomposed by the methods registered for this name
+    Compound(String),
+    // Planned for with the regular GOAP planner
+    Achieve(Goal),
+}
+
+impl Task {
+    pub fn primitive<S: AsRef<str>>(action: S) -> Self {
+        Task::Primitive {
+            action: action.as_ref().to_owned(),
+            argument: None,
+        }
+    }
+
+    pub fn primitive_with<S: AsRef<str>>(action: S, argument: Value) -> Self {
+        Task::Primitive {
+            action: action.as_ref().to_owned(),
+            argument: Some(argument),
+        }
+    }
+
+    pub fn compound<S: AsRef<str>>(name: S) -> Self {
+        Task::Compound(name.as_ref().to_owned())
+    }
+
+    pub fn achieve(goal: Goal) -> Self {
+        Task::Achieve(goal)
+    }
+}
+
+impl std::fmt::Debug for Task {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Task::Primitive { action, argument } => {
+                write!(f, "{}", step_label(action, argument.as_ref()))
+            }
+            Task::Compound(name) => write!(f, "[{}]", name),
+            Task::Achieve(goal) => write!(f, "achieve {:?}", goal),
+        }
+    }
+}
+
+pub struct Method {
+    name: String,
+    precondition: Goal,
+    subtasks: Vec<Task>,
+}
+
+impl Method {
+    pub fn new<S: AsRef<str>>(name: S) -> Self {
+        Method {
+            name: name.as_ref().to_owned(),
+            precondition: Goal::new(),
+            subtasks: vec![],
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn subtasks(&self) -> &[Task] {
+        &self.subtasks
+    }
+
+    pub fn with_precondition<S: AsRef<str>>(mut self, key: S, value: BoxedRequirement) -> Self {
+        self.precondition.insert(key, value);
+        self
+    }
+
+    pub fn with_subtask(mut self, task: Task) -> Self {
+        self.subtasks.push(task);
+        self
+    }
+
+    pub fn is_applicable(&self, state: &State) -> bool {
+        state.distance_to_goal(&self.precondition) == 0
+    }
+}
+
+pub struct HtnDomain {
+    methods: BTreeMap<String, Vec<Method>>,
+    max_depth: usize,
+}
+
+impl HtnDomain {
+    pub fn new() -> Self {
+        HtnDomain {
+            methods: BTreeMap::new(),
+            max_depth: 64,
+        }
+    }
+
+    // Methods of a task are tried in the order they were added
+    pub fn add_method<S: AsRef<str>>(&mut self, task: S, method: Method) {
+        self.methods
+            .entry(task.as_ref().to_owned())
+            .or_default()
+            .push(method);
+    }
+
+    pub fn with_method<S: AsRef<str>>(mut self, task: S, method: Method) -> Self {
+        self.add_method(task, method);
+        self
+    }
+
+    // Limits how deeply compound tasks may be nested, which stops
+    // recursive methods from decomposing forever
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    pub fn methods<S: AsRef<str>>(&self, task: S) -> &[Method] {
+        self.methods
+            .get(task.as_ref())
+            .map(|methods| &methods[..])
+            .unwrap_or(&[])
+    }
+}
+
+impl Default for HtnDomain {
+    fn default() -> Self {
+        HtnDomain::new()
+    }
+}
+
+// Decomposes `tasks` depth-first, backtracking over methods and over the
+// options of primitive tasks. `options` are used for `Task::Achieve` leaves,
+// and their constraints and schema bounds hold for primitive steps too
+pub fn plan_htn<'a>(
+    start: &State,
+    actions: &[Box<dyn Action + 'a>],
+    domain: &HtnDomain,
+    tasks: &[Task],
+    options: &PlanOptions,
+) -> Option<Plan> {
+    let start = prepare_checked(start, actions, options)?;
+    let context = Context {
+        actions,
+        domain,
+        options,
+    };
+    let agenda: Vec<(&Task, usize)> = tasks.iter().map(|task| (task, 0)).collect();
+    let (mut steps, cost) = context.decompose(&start, &agenda)?;
+    steps.reverse();
+
+    let mut nodes = vec![Node::State(start)];
+    nodes.extend(steps);
+    Some((nodes, cost))
+}
+
+struct Context<'c, 'a> {
+    actions: &'c [Box<dyn Action + 'a>],
+    domain: &'c HtnDomain,
+    options: &'c PlanOptions,
+}
+
+impl<'c, 'a> Context<'c, 'a> {
+    // Returns the steps in reverse order. Every task on the agenda carries
+    // the depth of the compound task it came from
+    fn decompose(&self, state: &State, agenda: &[(&Task, usize)]) -> Option<(Vec<Node>, u64)> {
+        let ((task, depth), rest) = match agenda.split_first() {
+            Some(split) => split,
+            None => return Some((vec![], 0)),
+        };
+        debug!("----- Decomposing {:?} -----", task);
+
+        match task {
+            Task::Primitive { action, argument } => {
+                let action = self.actions.iter().find(|other| &other.key() == action)?;
+                let mut options: Vec<_> = action
+                    .options(state)
+                    .into_iter()
+                    .filter(|(consequence, _)| {
+                        (argument.is_none() || consequence.argument == *argument)
+                            && self.options.check(&consequence.result).is_none()
+                            && can_meet_deadlines(&consequence.result, rest)
+                    })
+                    .collect();
+                options.sort_by_key(|(_, cost)| *cost);
+                for (consequence, cost) in options {
+                    if let Some((mut steps, rest_cost)) = self.decompose(&consequence.result, rest)
+                    {
+                        steps.push(Node::Consequence(consequence));
+                        return Some((steps, rest_cost + cost));
+                    }
+                }
+                None
+            }
+            Task::Compound(name) => {
+                if *depth >= self.domain.max_depth {
+                    return None;
+                }
+                for method in self.domain.methods(name) {
+                    if !method.is_applicable(state) {
+                        continue;
+                    }
+                    let mut expanded: Vec<(&Task, usize)> = method
+                        .subtasks
+                        .iter()
+                        .map(|subtask| (subtask, depth + 1))
+                        .collect();
+                    expanded.extend(rest);
+                    if let Some(result) = self.decompose(state, &expanded) {
+                        debug!("----- {:?} decomposed by {} -----", task, method.name);
+                        return Some(result);
+                    }
+                }
+                None
+            }
+            Task::Achieve(goal) => {
+                let (plan, plan_cost) = plan_with(state, self.actions, goal, self.options)?;
+                let reached = plan.last()?.state().clone();
+                let (mut steps, rest_cost) = self.decompose(&reached, rest)?;
+                steps.extend(plan.into_iter().skip(1).rev());
+                Some((steps, rest_cost + plan_cost))
+            }
+        }
+    }
+}
+
+// Steps before a `Task::Achieve` can already pass the time deadlines of its
+// goal, its cost deadlines only count the steps planned for it
+fn can_meet_deadlines(state: &State, agenda: &[(&Task, usize)]) -> bool {
+    agenda.iter().all(|(task, _)| match task {
+        Task::Achieve(goal) => goal.can_meet_deadlines(state, 0),
+        _ => true,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        constraint::ConstraintSet,
+        field::Field,
+        goal::Deadline,
+        requirement::CompareRequirement,
+        test_support::{equals, Add, Chop},
+    };
+
+    #[test]
+    fn max_depth_limits_nesting_not_siblings() {
+        let start = State::new().with_field("x", Field::from(0u64));
+        let actions: Vec<Box<dyn Action>> = vec![Box::new(Add(1))];
+        let domain = HtnDomain::new()
+            .with_method(
+                "step",
+                Method::new("add").with_subtask(Task::primitive("add")),
+            )
+            .with_max_depth(1);
+        let tasks: Vec<Task> = (0..5).map(|_| Task::compound("step")).collect();
+        let (_, cost) = plan_htn(&start, &actions, &domain, &tasks, &PlanOptions::new()).unwrap();
+        assert_eq!(cost, 5);
+
+        // Adds until x is 3, nesting one level deeper on every step
+        let fill = |max_depth| {
+            HtnDomain::new()
+                .with_method(
+                    "fill",
+                    Method::new("done").with_precondition(
+                        "x",
+                        Box::new(CompareRequirement::Equals(Field::from(3u64))),
+                    ),
+                )
+                .with_method(
+                    "fill",
+                    Method::new("more")
+                        .with_subtask(Task::primitive("add"))
+                        .with_subtask(Task::compound("fill")),
+                )
+                .with_max_depth(max_depth)
+        };
+        let tasks = vec![Task::compound("fill")];
+        let cost = |domain: &HtnDomain| {
+            plan_htn(&start, &actions, domain, &tasks, &PlanOptions::new()).map(|(_, cost)| cost)
+        };
+        assert_eq!(cost(&fill(4)), Some(3));
+        assert_eq!(cost(&fill(3)), None);
+    }
+
+    #[test]
+    fn primitive_steps_keep_constraints_and_deadlines() {
+        let actions: Vec<Box<dyn Action>> = vec![Box::new(Chop), Box::new(Add(1))];
+        let domain = HtnDomain::new();
+        let tasks = vec![Task::primitive("chop")];
+        let options = PlanOptions::new().with_constraints(ConstraintSet::new().with_constraint(
+            "stocked",
+            "wood",
+            Box::new(CompareRequirement::MoreThanEquals(Field::from(5u64))),
+        ));
+        let (nodes, cost) = plan_htn(&State::new(), &actions, &domain, &tasks, &options).unwrap();
+        assert_eq!(cost, 3);
+        assert_eq!(nodes[1].state().get("wood"), Some(Field::from(8u64)));
+
+        // Adding twice already passes the deadline of the goal that follows
+        let domain = HtnDomain::new()
+            .with_method(
+                "work",
+                Method::new("twice")
+                    .with_subtask(Task::primitive("add"))
+                    .with_subtask(Task::primitive("add")),
+            )
+            .with_method(
+                "work",
+                Method::new("once").with_subtask(Task::primitive("add")),
+            );
+        let goal = equals("x", 1).with_deadline(Deadline::time("clock", 1));
+        let tasks = vec![Task::compound("work"), Task::achieve(goal)];
+        let start = State::new()
+            .with_field("x", Field::from(0u64))
+            .with_field("clock", Field::from(0u64));
+        let (nodes, cost) =
+            plan_htn(&start, &actions, &domain, &tasks, &PlanOptions::new()).unwrap();
+        assert_eq!(cost, 1);
+        assert_eq!(nodes.len(), 2);
+    }
+}
@@ -0,0 +1,223 @@
+use std::fmt;
+use std::marker::PhantomData;
+
+use serde_json::Value;
+
+use crate::{
+    field::{Field, FieldType},
+    requirement::{BoxedRequirement, CompareRequirement},
+    schema::KeySchema,
+};
+
+// Rust types stored in a single field
+pub trait FieldValue: Sized {
+    fn field_type() -> FieldType;
+    fn into_field(self) -> Field;
+    fn from_field(field: &Field) -> Option<Self>;
+}
+
+impl FieldValue for bool {
+    fn field_type() -> FieldType {
+        FieldType::Bool
+    }
+
+    fn into_field(self) -> Field {
+        Field::Bool(self)
+    }
+
+    fn from_field(field: &Field) -> Option<Self> {
+        field.as_bool()
+    }
+}
+
+impl FieldValue for u64 {
+    fn field_type() -> FieldType {
+        FieldType::U64
+    }
+
+    fn into_field(self) -> Field {
+        Field::U64(self)
+    }
+
+    fn from_field(field: &Field) -> Option<Self> {
+        field.as_u64()
+    }
+}
+
+impl FieldValue for i64 {
+    fn field_type() -> FieldType {
+        FieldType::I64
+    }
+
+    fn into_field(self) -> Field {
+        Field::I64(self)
+    }
+
+    fn from_field(field: &Field) -> Option<Self> {
+        field.as_i64()
+    }
+}
+
+impl FieldValue for f64 {
+    fn field_type() -> FieldType {
+        FieldType::F64
+    }
+
+    fn into_field(self) -> Field {
+        Field::F64(self)
+    }
+
+    fn from_field(field: &Field) -> Option<Self> {
+        field.as_f64()
+    }
+}
+
+impl FieldValue for String {
+    fn field_type() -> FieldType {
+        FieldType::String
+    }
+
+    fn into_field(self) -> Field {
+        Field::String(self)
+    }
+
+    fn from_field(field: &Field) -> Option<Self> {
+        match field {
+            Field::String(value) => Some(value.clone()),
+            Field::Value(Value::String(value)) => Some(value.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl FieldValue for Value {
+    fn field_type() -> FieldType {
+        FieldType::Value
+    }
+
+    fn into_field(self) -> Field {
+        Field::Value(self)
+    }
+
+    fn from_field(field: &Field) -> Option<Self> {
+        field.as_value()
+    }
+}
+
+// A key with the type of its value, declared once so typos fail to compile:
+//
+//     const WOOD: Key<u64> = Key::new("wood");
+//
+// Keys are plain strings underneath, anything taking a string key takes a
+// `Key` as well
+pub struct Key<T> {
+    name: &'static str,
+    value: PhantomData<fn() -> T>,
+}
+
+impl<T> Key<T> {
+    pub const fn new(name: &'static str) -> Self {
+        Key {
+            name,
+            value: PhantomData,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl<T: FieldValue> Key<T> {
+    pub fn field_type(&self) -> FieldType {
+        T::field_type()
+    }
+
+    // Schema entry of the key's type
+    pub fn schema(&self) -> KeySchema {
+        KeySchema::new(T::field_type())
+    }
+
+    pub fn field(&self, value: T) -> Field {
+        value.into_field()
+    }
+
+    // Requirements compared against a value of the key's type
+    pub fn equals(&self, value: T) -> BoxedRequirement {
+        Box::new(CompareRequirement::Equals(value.into_field()))
+    }
+
+    pub fn at_least(&self, value: T) -> BoxedRequirement {
+        Box::new(CompareRequirement::MoreThanEquals(value.into_field()))
+    }
+
+    pub fn at_most(&self, value: T) -> BoxedRequirement {
+        Box::new(CompareRequirement::LessThanEquals(value.into_field()))
+    }
+}
+
+// Manual impls, derives would require `T` to implement the traits too
+impl<T> Clone for Key<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Key<T> {}
+
+impl<T> PartialEq for Key<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl<T> Eq for Key<T> {}
+
+impl<T> fmt::Debug for Key<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Key({:?})", self.name)
+    }
+}
+
+impl<T> AsRef<str> for Key<T> {
+    fn as_ref(&self) -> &str {
+        self.name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::State;
+
+    const WOOD: Key<u64> = Key::new("wood");
+    const NAME: Key<String> = Key::new("name");
+
+    #[test]
+    fn values_of_another_type_read_as_none() {
+        let mut state = State::new().with_field("wood", Field::from(true));
+        assert_eq!(state.value(WOOD), None);
+        assert_eq!(state.value(NAME), None);
+
+        // Setting replaces the value of the other type and returns it
+        assert_eq!(state.set(WOOD, 3), Some(Field::from(true)));
+        assert_eq!(state.value(WOOD), Some(3));
+        assert_eq!(state.get_as_u64("wood"), Some(3));
+
+        let state = state.with_field("name", Field::from(-1i64));
+        assert_eq!(state.value(NAME), None);
+        let state = state.with_value(NAME, "oak".to_owned());
+        assert_eq!(state.value(NAME), Some("oak".to_owned()));
+        assert_eq!(state.value(Key::<u64>::new("missing")), None);
+    }
+
+    #[test]
+    fn requirements_compare_the_keys_type() {
+        let state = State::new().with_value(WOOD, 4);
+        assert_eq!(state.distance_to_requirement(WOOD, &WOOD.at_least(4)), 0);
+        assert_eq!(state.distance_to_requirement(WOOD, &WOOD.at_most(2)), 2);
+        assert_eq!(state.distance_to_requirement(WOOD, &WOOD.equals(5)), 1);
+        assert_eq!(WOOD.field_type(), FieldType::U64);
+        assert_eq!(WOOD.schema().field_type(), FieldType::U64);
+    }
+}
@@ -2,9 +2,34 @@
 extern crate log;
 
 pub mod action;
+pub mod analysis;
+pub mod belief;
+pub mod blackboard;
+pub mod cache;
+pub mod constraint;
+pub mod convert;
 pub mod distance;
+pub mod explain;
 pub mod field;
 pub mod goal;
+pub mod htn;
+pub mod key;
+pub mod macro_action;
+pub mod mcts;
+pub mod multi_agent;
+pub mod parallel;
+pub mod partial_order;
 pub mod planner;
+pub mod repair;
 pub mod requirement;
+pub mod reservation;
+pub mod schema;
 pub mod state;
+pub mod stats;
+pub mod stochastic;
+pub mod temporal;
+pub mod trace;
+pub mod validation;
+
+#[cfg(test)]
+mod test_support;
@@ -0,0 +1,288 @@
+use std::collections::HashMap;
+
+use serde_json::Value;
+
+use crate::{
+    action::{step_label, Action, Consequence},
+    planner::{Node, Plan},
+    state::State,
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MacroStep {
+    pub action: String,
+    // Only options with this argument are chained, any option when `None`
+    pub argument: Option<Value>,
+}
+
+impl MacroStep {
+    pub fn label(&self) -> String {
+        step_label(&self.action, self.argument.as_ref())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MacroDefinition {
+    key: String,
+    steps: Vec<MacroStep>,
+    // Number of times the steps occurred in the mined plans
+    support: usize,
+}
+
+impl MacroDefinition {
+    pub fn new<S: AsRef<str>>(key: S) -> Self {
+        MacroDefinition {
+            key: key.as_ref().to_owned(),
+            steps: vec![],
+            support: 0,
+        }
+    }
+
+    pub fn with_step<S: AsRef<str>>(mut self, action: S, argument: Option<Value>) -> Self {
+        self.steps.push(MacroStep {
+            action: action.as_ref().to_owned(),
+            argument,
+        });
+        self
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn steps(&self) -> &[MacroStep] {
+        &self.steps
+    }
+
+    pub fn support(&self) -> usize {
+        self.support
+    }
+}
+
+// Chains the options of its component actions. The argument of each
+// consequence lists the option picked at every step, which is what
+// `expand_macros` uses to turn it back into primitive steps
+pub struct MacroAction<'m, 'a> {
+    definition: MacroDefinition,
+    actions: &'m [Box<dyn Action + 'a>],
+}
+
+impl<'m, 'a> MacroAction<'m, 'a> {
+    pub fn new(definition: MacroDefinition, actions: &'m [Box<dyn Action + 'a>]) -> Self {
+        MacroAction {
+            definition,
+            actions,
+        }
+    }
+
+    pub fn definition(&self) -> &MacroDefinition {
+        &self.definition
+    }
+
+    fn step_options(&self, step: &MacroStep, state: &State) -> Vec<(Consequence, u64)> {
+        match self
+            .actions
+            .iter()
+            .find(|action| action.key() == step.action)
+        {
+            Some(action) => action
+                .options(state)
+                .into_iter()
+                .filter(|(consequence, _)| {
+                    step.argument.is_none() || consequence.argument == step.argument
+                })
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    // Replays the recorded choices of a consequence offered by this macro
+    pub fn expand(
+        &self,
+        state: &State,
+        consequence: &Consequence,
+    ) -> Option<Vec<(Consequence, u64)>> {
+        if consequence.action != self.definition.key {
+            return None;
+        }
+        let choices = consequence.argument.as_ref()?.as_array()?;
+        if choices.len() != self.definition.steps.len() {
+            return None;
+        }
+
+        let mut state = state.clone();
+        let mut steps = vec![];
+        for (step, choice) in self.definition.steps.iter().zip(choices) {
+            let choice = choice.as_u64()? as usize;
+            let (step_consequence, cost) =
+                self.step_options(step, &state).into_iter().nth(choice)?;
+            state = step_consequence.result.clone();
+            steps.push((step_consequence, cost));
+        }
+        Some(steps)
+    }
+}
+
+impl<'m, 'a> Action for MacroAction<'m, 'a> {
+    fn key(&self) -> String {
+        self.definition.key.clone()
+    }
+
+    fn prepare(&self, state: &State) -> State {
+        let mut prepared_state = state.clone();
+        for step in &self.definition.steps {
+            if let Some(action) = self
+                .actions
+                .iter()
+                .find(|action| action.key() == step.action)
+            {
+                prepared_state = action.prepare(&prepared_state);
+            }
+        }
+        prepared_state
+    }
+
+    fn options(&self, state: &State) -> Vec<(Consequence, u64)> {
+        // Every combination of matching options, as (state, choices, cost)
+        let mut frontier = vec![(state.clone(), vec![], 0)];
+        for step in &self.definition.steps {
+            let mut next = vec![];
+            for (state, choices, cost) in frontier {
+                for (index, (consequence, step_cost)) in
+                    self.step_options(step, &state).into_iter().enumerate()
+                {
+                    let mut choices: Vec<usize> = choices.clone();
+                    choices.push(index);
+                    next.push((consequence.result, choices, cost + step_cost));
+                }
+            }
+            frontier = next;
+        }
+
+        frontier
+            .into_iter()
+            .map(|(result, choices, cost)| {
+                (
+                    Consequence {
+                        action: self.key(),
+                        argument: Some(Value::from(choices)),
+                        result,
+                    },
+                    cost,
+                )
+            })
+            .collect()
+    }
+
+    fn reads(&self) -> Vec<String> {
+        self.component_keys(|action| action.reads())
+    }
+
+    fn writes(&self) -> Vec<String> {
+        self.component_keys(|action| action.writes())
+    }
+}
+
+impl<'m, 'a> MacroAction<'m, 'a> {
+    fn component_keys(&self, keys: impl Fn(&dyn Action) -> Vec<String>) -> Vec<String> {
+        let mut all = vec![];
+        for step in &self.definition.steps {
+            if let Some(action) = self
+                .actions
+                .iter()
+                .find(|action| action.key() == step.action)
+            {
+                for key in keys(&**action) {
+                    if !all.contains(&key) {
+                        all.push(key);
+                    }
+                }
+            }
+        }
+        all
+    }
+}
+
+// Replaces the steps of `macros` in `plan` with their primitive steps
+pub fn expand_macros(plan: &Plan, macros: &[MacroAction]) -> Plan {
+    let (nodes, cost) = plan;
+    let mut expanded: Vec<Node> = vec![];
+    for node in nodes {
+        let consequence = match node {
+            Node::Consequence(consequence) => consequence,
+            Node::State(_) => {
+                expanded.push(node.clone());
+                continue;
+            }
+        };
+        let steps = expanded.last().and_then(|previous| {
+            macros
+                .iter()
+                .find_map(|macro_action| macro_action.expand(previous.state(), consequence))
+        });
+        match steps {
+            Some(steps) => expanded.extend(
+                steps
+                    .into_iter()
+                    .map(|(consequence, _)| Node::Consequence(consequence)),
+            ),
+            None => expanded.push(node.clone()),
+        }
+    }
+    (expanded, *cost)
+}
+
+// Finds runs of `min_length` to `max_length` consecutive steps that occur
+// at least `min_support` times over all plans, most frequent and longest
+// first
+pub fn mine_macros(
+    plans: &[Plan],
+    min_length: usize,
+    max_length: usize,
+    min_support: usize,
+) -> Vec<MacroDefinition> {
+    let mut counts: HashMap<String, (Vec<MacroStep>, usize)> = HashMap::new();
+    for (nodes, _) in plans {
+        let steps: Vec<MacroStep> = nodes
+            .iter()
+            .filter_map(|node| match node {
+                Node::Consequence(consequence) => Some(MacroStep {
+                    action: consequence.action.clone(),
+                    argument: consequence.argument.clone(),
+                }),
+                Node::State(_) => None,
+            })
+            .collect();
+        for length in min_length.max(1)..=max_length {
+            for window in steps.windows(length) {
+                let key = macro_key(window);
+                counts.entry(key).or_insert_with(|| (window.to_vec(), 0)).1 += 1;
+            }
+        }
+    }
+
+    let mut definitions: Vec<MacroDefinition> = counts
+        .into_iter()
+        .filter(|(_, (_, support))| *support >= min_support)
+        .map(|(key, (steps, support))| MacroDefinition {
+            key,
+            steps,
+            support,
+        })
+        .collect();
+    definitions.sort_by(|a, b| {
+        b.support
+            .cmp(&a.support)
+            .then_with(|| b.steps.len().cmp(&a.steps.len()))
+            .then_with(|| a.key.cmp(&b.key))
+    });
+    definitions
+}
+
+fn macro_key(steps: &[MacroStep]) -> String {
+    steps
+        .iter()
+        .map(|step| step.label())
+        .collect::<Vec<_>>()
+        .join("+")
+}
@@ -0,0 +1,475 @@
+use std::time::{Duration, Instant};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    action::{Action, Consequence, Outcome},
+    constraint::ConstraintSet,
+    goal::Goal,
+    planner::{prepare_checked, Node, Plan, PlanOptions},
+    schema::Schema,
+    state::State,
+};
+
+#[derive(Clone, Debug)]
+pub struct MctsOptions {
+    iterations: u64,
+    time_limit: Option<Duration>,
+    max_depth: usize,
+    exploration: f64,
+    seed: Option<u64>,
+    plan_options: PlanOptions,
+}
+
+impl Default for MctsOptions {
+    fn default() -> Self {
+        MctsOptions::new()
+    }
+}
+
+impl MctsOptions {
+    pub fn new() -> Self {
+        MctsOptions {
+            iterations: 1000,
+            time_limit: None,
+            max_depth: 32,
+            exploration: std::f64::consts::SQRT_2,
+            seed: None,
+            plan_options: PlanOptions::new(),
+        }
+    }
+
+    pub fn iterations(&self) -> u64 {
+        self.iterations
+    }
+
+    pub fn time_limit(&self) -> Option<Duration> {
+        self.time_limit
+    }
+
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    pub fn exploration(&self) -> f64 {
+        self.exploration
+    }
+
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    pub fn plan_options(&self) -> &PlanOptions {
+        &self.plan_options
+    }
+
+    pub fn schema(&self) -> Option<&Schema> {
+        self.plan_options.schema()
+    }
+
+    pub fn constraints(&self) -> &ConstraintSet {
+        self.plan_options.constraints()
+    }
+
+    pub fn with_iterations(mut self, iterations: u64) -> Self {
+        self.iterations = iterations;
+        self
+    }
+
+    // Stop iterating once the search ran for this long
+    pub fn with_time_limit(mut self, time_limit: Duration) -> Self {
+        self.time_limit = Some(time_limit);
+        self
+    }
+
+    // Maximum number of steps from the start, in the tree and in rollouts
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    // UCT exploration constant, higher values try less visited choices more
+    pub fn with_exploration(mut self, exploration: f64) -> Self {
+        self.exploration = exploration;
+        self
+    }
+
+    // Makes rollouts and the sampled outcomes reproducible
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    // Constraints and schema of the options apply like in `plan_with`, a
+    // choice is left out when one of its outcomes violates them. The search
+    // budget of the options doesn't apply
+    pub fn with_plan_options(mut self, plan_options: PlanOptions) -> Self {
+        self.plan_options = plan_options;
+        self
+    }
+
+    pub fn with_constraints(mut self, constraints: ConstraintSet) -> Self {
+        self.plan_options = self.plan_options.with_constraints(constraints);
+        self
+    }
+
+    pub fn with_schema(mut self, schema: Schema) -> Self {
+        self.plan_options = self.plan_options.with_schema(schema);
+        self
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MctsResult {
+    // Consequence of the most visited choice at the start
+    pub best: Option<Consequence>,
+    // Most visited choices from the start, following the most probable
+    // outcome of each
+    pub plan: Plan,
+    // Average reward of the iterations through the best choice
+    pub reward: f64,
+    pub iterations: u64,
+}
+
+// Rewards rollouts with `1 / (1 + cost + distance to the goal)`
+pub fn plan_mcts<'a>(
+    start: &State,
+    actions: &[Box<dyn Action + 'a>],
+    goal: &Goal,
+    options: &MctsOptions,
+) -> MctsResult {
+    plan_mcts_with_reward(start, actions, goal, options, &|state, cost| {
+        1.0 / (1.0 + cost as f64 + state.distance_to_goal(goal) as f64)
+    })
+}
+
+// UCT over `Action::outcomes`, outcomes are sampled by their probability.
+// `reward` gets the last state and the total cost of every rollout, which
+// ends at the goal, at a dead end or at the maximum depth. Higher rewards
+// are better and should stay within 0 to 1 for the default exploration
+pub fn plan_mcts_with_reward<'a>(
+    start: &State,
+    actions: &[Box<dyn Action + 'a>],
+    goal: &Goal,
+    options: &MctsOptions,
+    reward: &dyn Fn(&State, u64) -> f64,
+) -> MctsResult {
+    let start = match prepare_checked(start, actions, &options.plan_options) {
+        Some(prepared) if options.plan_options.fits_schema(goal) => prepared,
+        // Without a choice when the start or the goal doesn't fit the schema
+        _ => {
+            return MctsResult {
+                best: None,
+                plan: (vec![Node::State(start.clone())], 0),
+                reward: 0.0,
+                iterations: 0,
+            }
+        }
+    };
+    let mut search = Mcts {
+        actions,
+        goal,
+        options,
+        reward,
+        rng: match options.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        },
+        nodes: vec![TreeNode::new(start.clone(), 0)],
+    };
+
+    let started = Instant::now();
+    let mut iterations = 0;
+    if start.distance_to_goal(goal) > 0 {
+        while iterations < options.iterations
+            && !matches!(options.time_limit, Some(limit) if started.elapsed() >= limit)
+        {
+            search.iterate();
+            iterations += 1;
+        }
+    }
+    debug!("----- MCTS ran {} iterations -----", iterations);
+
+    let mut result = search.principal_variation(start);
+    result.iterations = iterations;
+    result
+}
+
+struct Edge {
+    outcomes: Vec<Outcome>,
+    cost: u64,
+    visits: u64,
+    total_reward: f64,
+    // Tree node of every outcome, once it was sampled
+    children: Vec<Option<usize>>,
+}
+
+impl Edge {
+    fn mean(&self) -> f64 {
+        if self.visits == 0 {
+            0.0
+        } else {
+            self.total_reward / self.visits as f64
+        }
+    }
+
+    fn most_probable(&self) -> Option<usize> {
+        (0..self.outcomes.len()).max_by(|&a, &b| {
+            self.outcomes[a]
+                .probability
+                .partial_cmp(&self.outcomes[b].probability)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(b.cmp(&a))
+        })
+    }
+}
+
+struct TreeNode {
+    state: State,
+    depth: usize,
+    visits: u64,
+    // Filled in the first time the node is selected
+    edges: Option<Vec<Edge>>,
+}
+
+impl TreeNode {
+    fn new(state: State, depth: usize) -> Self {
+        TreeNode {
+            state,
+            depth,
+            visits: 0,
+            edges: None,
+        }
+    }
+}
+
+struct Mcts<'s, 'a> {
+    actions: &'s [Box<dyn Action + 'a>],
+    goal: &'s Goal,
+    options: &'s MctsOptions,
+    reward: &'s dyn Fn(&State, u64) -> f64,
+    rng: StdRng,
+    nodes: Vec<TreeNode>,
+}
+
+impl<'s, 'a> Mcts<'s, 'a> {
+    fn choices(&self, state: &State) -> Vec<(Vec<Outcome>, u64)> {
+        self.actions
+            .iter()
+            .flat_map(|action| action.outcomes(state))
+            .filter(|(outcomes, _)| {
+                !outcomes.is_empty()
+                    && outcomes.iter().all(|outcome| {
+                        self.options
+                            .plan_options
+                            .check(&outcome.consequence.result)
+                            .is_none()
+                    })
+            })
+            .collect()
+    }
+
+    fn is_terminal(&self, state: &State, depth: usize) -> bool {
+        depth >= self.options.max_depth || state.distance_to_goal(self.goal) == 0
+    }
+
+    fn sample(&mut self, outcomes: &[Outcome]) -> usize {
+        let total: f64 = outcomes.iter().map(|outcome| outcome.probability).sum();
+        let mut remaining = self.rng.gen::<f64>() * total;
+        for (index, outcome) in outcomes.iter().enumerate() {
+            remaining -= outcome.probability;
+            if remaining < 0.0 {
+                return index;
+            }
+        }
+        outcomes.len() - 1
+    }
+
+    fn select(&mut self, node: usize) -> Option<usize> {
+        if self.nodes[node].edges.is_none() {
+            let edges = self
+                .choices(&self.nodes[node].state)
+                .into_iter()
+                .map(|(outcomes, cost)| Edge {
+                    children: vec![None; outcomes.len()],
+                    outcomes,
+                    cost,
+                    visits: 0,
+                    total_reward: 0.0,
+                })
+                .collect();
+            self.nodes[node].edges = Some(edges);
+        }
+
+        let visits = self.nodes[node].visits;
+        let edges = self.nodes[node].edges.as_ref()?;
+        if edges.is_empty() {
+            return None;
+        }
+        let unvisited: Vec<usize> = (0..edges.len())
+            .filter(|&edge| edges[edge].visits == 0)
+            .collect();
+        if !unvisited.is_empty() {
+            return Some(unvisited[self.rng.gen_range(0..unvisited.len())]);
+        }
+
+        let exploration = self.options.exploration;
+        let uct = |edge: &Edge| {
+            edge.mean() + exploration * ((visits as f64).ln() / edge.visits as f64).sqrt()
+        };
+        (0..edges.len()).max_by(|&a, &b| {
+            uct(&edges[a])
+                .partial_cmp(&uct(&edges[b]))
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then(b.cmp(&a))
+        })
+    }
+
+    // Selects down the tree until a new node is added, rolls out from there
+    // and backs the reward up along the selected choices
+    fn iterate(&mut self) {
+        let mut path: Vec<(usize, usize)> = vec![];
+        let mut node = 0;
+        let mut cost = 0;
+        loop {
+            let TreeNode { state, depth, .. } = &self.nodes[node];
+            if self.is_terminal(state, *depth) {
+                break;
+            }
+            let edge = match self.select(node) {
+                Some(edge) => edge,
+                None => break,
+            };
+            let outcomes = self.nodes[node].edges.as_ref().unwrap()[edge]
+                .outcomes
+                .clone();
+            let outcome = self.sample(&outcomes);
+            let depth = self.nodes[node].depth + 1;
+            let new_child = self.nodes.len();
+            let edge_ref = &mut self.nodes[node].edges.as_mut().unwrap()[edge];
+            cost += edge_ref.cost;
+            path.push((node, edge));
+            match edge_ref.children[outcome] {
+                Some(child) => node = child,
+                None => {
+                    edge_ref.children[outcome] = Some(new_child);
+                    let state = outcomes[outcome].consequence.result.clone();
+                    self.nodes.push(TreeNode::new(state, depth));
+                    node = new_child;
+                    break;
+                }
+            }
+        }
+
+        let (last, rollout_cost) = self.rollout(node);
+        let reward = (self.reward)(&last, cost + rollout_cost);
+        self.nodes[node].visits += 1;
+        for (node, edge) in path {
+            let node = &mut self.nodes[node];
+            node.visits += 1;
+            let edge = &mut node.edges.as_mut().unwrap()[edge];
+            edge.visits += 1;
+            edge.total_reward += reward;
+        }
+    }
+
+    // Random choices until the rollout ends, returns the last state and the
+    // cost of the rollout
+    fn rollout(&mut self, node: usize) -> (State, u64) {
+        let mut state = self.nodes[node].state.clone();
+        let mut depth = self.nodes[node].depth;
+        let mut cost = 0;
+        while !self.is_terminal(&state, depth) {
+            let mut choices = self.choices(&state);
+            if choices.is_empty() {
+                break;
+            }
+            let (outcomes, choice_cost) = choices.swap_remove(self.rng.gen_range(0..choices.len()));
+            let outcome = self.sample(&outcomes);
+            state = outcomes[outcome].consequence.result.clone();
+            cost += choice_cost;
+            depth += 1;
+        }
+        (state, cost)
+    }
+
+    fn principal_variation(&self, start: State) -> MctsResult {
+        let mut nodes = vec![Node::State(start)];
+        let mut cost = 0;
+        let mut reward = 0.0;
+        let mut node = 0;
+        while let Some(edges) = &self.nodes[node].edges {
+            let edge = match edges
+                .iter()
+                .enumerate()
+                .filter(|(_, edge)| edge.visits > 0)
+                .max_by(|(a_index, a), (b_index, b)| {
+                    a.visits.cmp(&b.visits).then(b_index.cmp(a_index))
+                }) {
+                Some((_, edge)) => edge,
+                None => break,
+            };
+            let outcome = match edge.most_probable() {
+                Some(outcome) => outcome,
+                None => break,
+            };
+            if node == 0 {
+                reward = edge.mean();
+            }
+            nodes.push(Node::Consequence(
+                edge.outcomes[outcome].consequence.clone(),
+            ));
+            cost += edge.cost;
+            match edge.children[outcome] {
+                Some(child) => node = child,
+                None => break,
+            }
+        }
+
+        let best = match nodes.get(1) {
+            Some(Node::Consequence(consequence)) => Some(consequence.clone()),
+            _ => None,
+        };
+        MctsResult {
+            best,
+            plan: (nodes, cost),
+            reward,
+            iterations: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{field::Field, requirement::CompareRequirement, test_support::Heat};
+
+    #[test]
+    fn choices_violating_the_constraints_are_left_out() {
+        let actions: Vec<Box<dyn Action>> = vec![Box::new(Heat)];
+        let goal = Goal::new().with_req(
+            "t",
+            Box::new(CompareRequirement::MoreThanEquals(Field::from(2.0))),
+        );
+        let constraints = ConstraintSet::new().with_constraint(
+            "cool",
+            "t",
+            Box::new(CompareRequirement::LessThanEquals(Field::from(2.0))),
+        );
+        let start = State::new().with_field("t", Field::from(0.0));
+        let options = MctsOptions::new()
+            .with_seed(7)
+            .with_iterations(200)
+            .with_constraints(constraints.clone());
+        let result = plan_mcts(&start, &actions, &goal, &options);
+        let (nodes, cost) = result.plan;
+        assert_eq!(cost, 2);
+        assert_eq!(
+            nodes.last().unwrap().state().get("t"),
+            Some(Field::from(2.0))
+        );
+        assert!(nodes
+            .iter()
+            .all(|node| constraints.check(node.state()).is_none()));
+    }
+}
@@ -0,0 +1,427 @@
+use serde_json::Value;
+
+use crate::{
+    action::{Action, Consequence, Outcome},
+    field::Field,
+    goal::Goal,
+    planner::{plan_with, prepare_checked, Node, Plan, PlanOptions},
+    state::State,
+    temporal::{schedule_with, Schedule, ScheduledStep},
+    validation::same_consequence,
+};
+
+// An action carried out by `agent`. Its key and the action of its
+// consequences are tagged as `agent/key`, and the agent is one of the
+// resources it holds, so each agent does one thing at a time
+pub struct AgentAction<'a> {
+    agent: String,
+    action: Box<dyn Action + 'a>,
+}
+
+impl<'a> AgentAction<'a> {
+    pub fn new<S: AsRef<str>>(agent: S, action: Box<dyn Action + 'a>) -> Self {
+        AgentAction {
+            agent: agent.as_ref().to_owned(),
+            action,
+        }
+    }
+
+    pub fn agent(&self) -> &str {
+        &self.agent
+    }
+
+    fn tag(&self, mut consequence: Consequence) -> Consequence {
+        consequence.action = format!("{}/{}", self.agent, consequence.action);
+        consequence
+    }
+
+    fn untag(&self, consequence: &Consequence) -> Consequence {
+        let mut consequence = consequence.clone();
+        if let Some((_, action)) = split_tag(&consequence.action) {
+            consequence.action = action.to_owned();
+        }
+        consequence
+    }
+}
+
+impl<'a> Action for AgentAction<'a> {
+    fn key(&self) -> String {
+        format!("{}/{}", self.agent, self.action.key())
+    }
+
+    fn prepare(&self, state: &State) -> State {
+        self.action.prepare(state)
+    }
+
+    fn options(&self, state: &State) -> Vec<(Consequence, u64)> {
+        self.action
+            .options(state)
+            .into_iter()
+            .map(|(consequence, cost)| (self.tag(consequence), cost))
+            .collect()
+    }
+
+    fn reads(&self) -> Vec<String> {
+        self.action.reads()
+    }
+
+    fn writes(&self) -> Vec<String> {
+        self.action.writes()
+    }
+
+    fn duration(&self, consequence: &Consequence, cost: u64) -> u64 {
+        self.action.duration(&self.untag(consequence), cost)
+    }
+
+    fn resources(&self, consequence: &Consequence) -> Vec<String> {
+        let mut resources = self.action.resources(&self.untag(consequence));
+        resources.push(format!("agent:{}", self.agent));
+        resources
+    }
+
+    fn observes(&self) -> Vec<String> {
+        self.action.observes()
+    }
+
+    fn outcomes(&self, state: &State) -> Vec<(Vec<Outcome>, u64)> {
+        self.action
+            .outcomes(state)
+            .into_iter()
+            .map(|(outcomes, cost)| {
+                let outcomes = outcomes
+                    .into_iter()
+                    .map(|outcome| Outcome {
+                        consequence: self.tag(outcome.consequence),
+                        probability: outcome.probability,
+                    })
+                    .collect();
+                (outcomes, cost)
+            })
+            .collect()
+    }
+}
+
+// Agent of a tagged action key or consequence action
+pub fn agent_of(action: &str) -> Option<&str> {
+    split_tag(action).map(|(agent, _)| agent)
+}
+
+fn split_tag(action: &str) -> Option<(&str, &str)> {
+    let separator = action.find('/')?;
+    Some((&action[..separator], &action[separator + 1..]))
+}
+
+#[derive(Clone, Debug)]
+pub struct JointPlan {
+    pub plan: Plan,
+    // Steps of different agents overlap wherever they don't depend on
+    // each other
+    pub schedule: Schedule,
+}
+
+impl JointPlan {
+    pub fn steps_of(&self, agent: &str) -> Vec<&ScheduledStep> {
+        self.schedule
+            .steps
+            .iter()
+            .filter(|step| agent_of(&step.action) == Some(agent))
+            .collect()
+    }
+}
+
+// Plans for all agents at once over the shared state. `actions` should be
+// `AgentAction`s, the plan is scheduled so agents work in parallel
+pub fn plan_joint<'a>(
+    start: &State,
+    actions: &[Box<dyn Action + 'a>],
+    goal: &Goal,
+    options: &PlanOptions,
+) -> Option<JointPlan> {
+    let plan = plan_with(start, actions, goal, options)?;
+    let schedule = schedule_with(start, actions, &plan, options)?;
+    Some(JointPlan { plan, schedule })
+}
+
+#[derive(Clone, Debug)]
+pub struct Conflict {
+    pub agent: String,
+    // Index of the step's node in the agent's own plan
+    pub step: usize,
+    pub action: String,
+    // Other agents whose steps ran before it in the merged plan
+    pub preceded_by: Vec<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct MultiAgentPlan {
+    pub joint: JointPlan,
+    // Conflicts found while merging the plans of the agents, each resolved
+    // by replanning for that agent after the merged steps
+    pub conflicts: Vec<Conflict>,
+    // Agents whose goal was undone by the steps of other agents and who
+    // planned again at the end, in the order they did
+    pub replanned: Vec<String>,
+}
+
+// Plans for every agent on its own, using only the actions tagged with its
+// name, then merges the plans by interleaving their steps one round at a
+// time. A step the merged state no longer offers, such as buying an axe
+// another agent already bought, is a conflict; the agent's remaining steps
+// are dropped and it replans from the merged state once the others are
+// done. Agents are resolved in the order they are given. Agents whose goal
+// no longer holds at the end, because another agent undid it, replan too,
+// for as many rounds as there are agents. Returns `None` if an agent can't
+// reach its goal
+pub fn plan_agents<'a>(
+    start: &State,
+    actions: &[Box<dyn Action + 'a>],
+    goals: &[(String, Goal)],
+    options: &PlanOptions,
+) -> Option<MultiAgentPlan> {
+    let start = prepare_checked(start, actions, options)?;
+    let mut plans = vec![];
+    for (agent, goal) in goals {
+        let agent_actions = actions_of(actions, agent);
+        plans.push(plan_with(&start, &agent_actions[..], goal, options)?);
+    }
+
+    let ((mut nodes, mut cost), conflicts) = merge_plans(&start, actions, &plans);
+    for conflict in &conflicts {
+        let (_, goal) = goals.iter().find(|(agent, _)| *agent == conflict.agent)?;
+        let agent_actions = actions_of(actions, &conflict.agent);
+        let state = nodes.last()?.state().clone();
+        debug!("----- Replanning for {} -----", conflict.agent);
+        let (replanned, replanned_cost) = plan_with(&state, &agent_actions[..], goal, options)?;
+        nodes.extend(replanned.into_iter().skip(1));
+        cost += replanned_cost;
+    }
+
+    let mut replanned = vec![];
+    for _ in 0..goals.len() {
+        let mut all_reached = true;
+        for (agent, goal) in goals {
+            let state = nodes.last()?.state().clone();
+            if state.distance_to_goal(goal) == 0 {
+                continue;
+            }
+            all_reached = false;
+            let agent_actions = actions_of(actions, agent);
+            debug!("----- Replanning for {}, its goal was undone -----", agent);
+            let (agent_plan, agent_cost) = plan_with(&state, &agent_actions[..], goal, options)?;
+            nodes.extend(agent_plan.into_iter().skip(1));
+            cost += agent_cost;
+            replanned.push(agent.clone());
+        }
+        if all_reached {
+            break;
+        }
+    }
+    let end = nodes.last()?.state();
+    if goals
+        .iter()
+        .any(|(_, goal)| end.distance_to_goal(goal) != 0)
+    {
+        return None;
+    }
+
+    let plan = (nodes, cost);
+    let schedule = schedule_with(&start, actions, &plan, options)?;
+    Some(MultiAgentPlan {
+        joint: JointPlan { plan, schedule },
+        conflicts,
+        replanned,
+    })
+}
+
+// Interleaves the steps of `plans`, all starting at `start`, into a single
+// plan over the shared state. Each step is carried out again in the merged
+// state, as the identical consequence if it's still offered or else as the
+// option of the same action and argument that changes the same keys the
+// same way, numbers by the same amount. The first step of a plan that
+// isn't offered anymore is a conflict and ends that plan
+pub fn merge_plans<'a>(
+    start: &State,
+    actions: &[Box<dyn Action + 'a>],
+    plans: &[Plan],
+) -> (Plan, Vec<Conflict>) {
+    let mut nodes = vec![Node::State(start.clone())];
+    let mut cost = 0;
+    let mut conflicts = vec![];
+    let mut blocked = vec![false; plans.len()];
+    let longest = plans
+        .iter()
+        .map(|(steps, _)| steps.len())
+        .max()
+        .unwrap_or(0);
+    for step in 1..longest {
+        for (index, (steps, _)) in plans.iter().enumerate() {
+            if blocked[index] {
+                continue;
+            }
+            let planned = match steps.get(step) {
+                Some(Node::Consequence(consequence)) => consequence,
+                _ => continue,
+            };
+            let state = nodes.last().unwrap().state();
+            let planned_changes = changes(steps[step - 1].state(), &planned.result);
+            let offered = actions
+                .iter()
+                .find(|action| action.key() == planned.action)
+                .and_then(|action| {
+                    let mut options = action.options(state);
+                    let index = options
+                        .iter()
+                        .position(|(consequence, _)| same_consequence(consequence, planned))
+                        .or_else(|| {
+                            options.iter().position(|(consequence, _)| {
+                                consequence.action == planned.action
+                                    && consequence.argument == planned.argument
+                                    && changes(state, &consequence.result) == planned_changes
+                            })
+                        })?;
+                    Some(options.swap_remove(index))
+                });
+            match offered {
+                Some((consequence, step_cost)) => {
+                    nodes.push(Node::Consequence(consequence));
+                    cost += step_cost;
+                }
+                None => {
+                    blocked[index] = true;
+                    let agent = agent_of(&planned.action).unwrap_or("");
+                    let mut preceded_by: Vec<String> = vec![];
+                    for node in &nodes[1..] {
+                        if let Node::Consequence(consequence) = node {
+                            if let Some(other) = agent_of(&consequence.action) {
+                                if other != agent && !preceded_by.iter().any(|known| known == other)
+                                {
+                                    preceded_by.push(other.to_owned());
+                                }
+                            }
+                        }
+                    }
+                    conflicts.push(Conflict {
+                        agent: agent.to_owned(),
+                        step,
+                        action: planned.action.clone(),
+                        preceded_by,
+                    });
+                }
+            }
+        }
+    }
+    ((nodes, cost), conflicts)
+}
+
+// How a step changes a key, numbers by the amount they change
+#[derive(Debug, PartialEq)]
+enum Change {
+    By(f64),
+    To(Option<Field>),
+}
+
+fn changes(before: &State, after: &State) -> Vec<(String, Change)> {
+    let mut keys: Vec<&String> = before
+        .iter()
+        .chain(after.iter())
+        .map(|(key, _)| key)
+        .collect();
+    keys.sort();
+    keys.dedup();
+    keys.into_iter()
+        .filter_map(|key| {
+            let (old, new) = (before.get(key), after.get(key));
+            if old == new {
+                return None;
+            }
+            let change = match (old.as_ref().and_then(number), new.as_ref().and_then(number)) {
+                (Some(old), Some(new)) => Change::By(new - old),
+                _ => Change::To(new),
+            };
+            Some((key.clone(), change))
+        })
+        .collect()
+}
+
+fn number(field: &Field) -> Option<f64> {
+    match field {
+        Field::U64(value) => Some(*value as f64),
+        Field::I64(value) => Some(*value as f64),
+        Field::F64(value) => Some(*value),
+        Field::Value(Value::Number(value)) => value.as_f64(),
+        _ => None,
+    }
+}
+
+fn actions_of<'s, 'a>(
+    actions: &'s [Box<dyn Action + 'a>],
+    agent: &str,
+) -> Vec<Box<dyn Action + 's>> {
+    actions
+        .iter()
+        .filter(|action| agent_of(&action.key()) == Some(agent))
+        .map(|action| Box::new(&**action) as Box<dyn Action + 's>)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{at_least, consequence, Chop};
+
+    // Saws all of at least 8 wood into a plank
+    struct Saw;
+
+    impl Action for Saw {
+        fn key(&self) -> String {
+            "saw".to_owned()
+        }
+
+        fn options(&self, state: &State) -> Vec<(Consequence, u64)> {
+            let wood = state.get_as_u64("wood").unwrap_or(0);
+            let planks = state.get_as_u64("planks").unwrap_or(0);
+            if wood < 8 {
+                return vec![];
+            }
+            let result = state
+                .with_field("wood", Field::from(0u64))
+                .with_field("planks", Field::from(planks + 1));
+            vec![(consequence(self, result), 1)]
+        }
+    }
+
+    #[test]
+    fn merged_steps_keep_their_changes_and_undone_goals_are_replanned() {
+        let actions: Vec<Box<dyn Action>> = vec![
+            Box::new(AgentAction::new("a", Box::new(Chop))),
+            Box::new(AgentAction::new("b", Box::new(Chop))),
+            Box::new(AgentAction::new("b", Box::new(Saw))),
+        ];
+        let goals = vec![
+            ("a".to_owned(), at_least("wood", 8)),
+            ("b".to_owned(), at_least("planks", 1)),
+        ];
+        let start = State::new()
+            .with_field("wood", Field::from(0u64))
+            .with_field("planks", Field::from(0u64));
+        let plan = plan_agents(&start, &actions, &goals, &PlanOptions::new()).unwrap();
+
+        let nodes = &plan.joint.plan.0;
+        let steps: Vec<&str> = nodes[1..]
+            .iter()
+            .map(|node| match node {
+                Node::Consequence(consequence) => consequence.action.as_str(),
+                Node::State(_) => "",
+            })
+            .collect();
+        assert_eq!(steps, vec!["a/chop", "b/chop", "b/saw", "a/chop"]);
+        // b chopped 8 wood like it planned, not the first option offered
+        assert_eq!(nodes[2].state().get_as_u64("wood"), Some(16));
+        // Sawing used up the wood a chopped
+        assert!(plan.conflicts.is_empty());
+        assert_eq!(plan.replanned, vec!["a".to_owned()]);
+        let end = nodes.last().unwrap().state();
+        assert_eq!(end.get_as_u64("wood"), Some(8));
+        assert_eq!(end.get_as_u64("planks"), Some(1));
+    }
+}
@@ -0,0 +1,196 @@
+use std::time::{Duration, Instant};
+
+use rayon::prelude::*;
+
+use crate::{
+    action::{Action, Consequence, SyncAction},
+    goal::{Goal, SyncGoal},
+    planner::{astar, plan_with, prepare_checked, Node, Plan, PlanOptions, Target},
+    state::State,
+    stats::SearchStats,
+};
+
+pub struct PlanJob {
+    pub start: State,
+    pub goal: SyncGoal,
+    // Per-job seed and budget
+    pub options: PlanOptions,
+}
+
+impl PlanJob {
+    pub fn new(start: State, goal: SyncGoal) -> Self {
+        PlanJob {
+            start,
+            goal,
+            options: PlanOptions::new(),
+        }
+    }
+
+    pub fn with_options(mut self, options: PlanOptions) -> Self {
+        self.options = options;
+        self
+    }
+}
+
+// Plans every job on the global rayon pool, results are in job order
+pub fn plan_many<'a>(actions: &[SyncAction<'a>], jobs: &[PlanJob]) -> Vec<Option<Plan>> {
+    jobs.par_iter()
+        .map(|job| {
+            let actions = borrow_actions(actions);
+            plan_with(&job.start, &actions[..], &job.goal.goal(), &job.options)
+        })
+        .collect()
+}
+
+// Same as `plan_many`, but on the given pool instead of the global one
+pub fn plan_many_in<'a>(
+    pool: &rayon::ThreadPool,
+    actions: &[SyncAction<'a>],
+    jobs: &[PlanJob],
+) -> Vec<Option<Plan>> {
+    pool.install(|| plan_many(actions, jobs))
+}
+
+// Calls `Action::options` for all actions of the expanded nodes in
+// parallel. Results are merged in the same order as the sequential planner,
+// so the plan is identical to `plan_with` for the same options. Raising the
+// batch size expands several of the best nodes at once; a goal is still
+// only accepted once it is the cheapest open node, so plans stay optimal
+// whenever the heuristic is admissible
+pub fn plan_parallel<'a>(
+    start: &State,
+    actions: &[SyncAction<'a>],
+    goal: &Goal,
+    options: &PlanOptions,
+) -> (Option<Plan>, SearchStats) {
+    let mut stats = SearchStats::new();
+    if !options.fits_schema(goal) {
+        return (None, stats);
+    }
+    let prepare_start = Instant::now();
+    let start = prepare_checked(start, &borrow_actions(actions)[..], options);
+    stats.prepare_time += prepare_start.elapsed();
+    let start = match start {
+        Some(start) => start,
+        None => return (None, stats),
+    };
+    let plan = astar(
+        start,
+        &|batch, stats| expand_parallel(batch, actions, stats),
+        &Target::goal(goal),
+        options,
+        &mut stats,
+        None,
+    );
+    (plan, stats)
+}
+
+type ActionOptions = (String, Vec<(Consequence, u64)>, Duration);
+
+fn expand_parallel<'a>(
+    batch: &[&Node],
+    actions: &[SyncAction<'a>],
+    stats: &mut SearchStats,
+) -> Vec<Vec<(Node, u64)>> {
+    let options: Vec<Vec<ActionOptions>> = batch
+        .par_iter()
+        .map(|node| {
+            actions
+                .par_iter()
+                .map(|action| {
+                    let options_start = Instant::now();
+                    let options = action.options(node.state());
+                    (action.key(), options, options_start.elapsed())
+                })
+                .collect()
+        })
+        .collect();
+
+    options
+        .into_iter()
+        .map(|per_action| {
+            let mut consequences = vec![];
+            for (key, options, elapsed) in per_action {
+                stats.options_time += elapsed;
+                *stats.action_expansions.entry(key).or_insert(0) += options.len() as u64;
+                consequences.extend(
+                    options
+                        .into_iter()
+                        .map(|(consequence, cost)| (Node::Consequence(consequence), cost)),
+                );
+            }
+            consequences
+        })
+        .collect()
+}
+
+pub(crate) fn borrow_actions<'s, 'a>(actions: &'s [SyncAction<'a>]) -> Vec<Box<dyn Action + 's>> {
+    actions
+        .iter()
+        .map(|action| Box::new(&**action) as Box<dyn Action + 's>)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::{
+        field::{Field, FieldType},
+        requirement::{CompareRequirement, Requirement},
+        test_support::Add,
+    };
+
+    // Counts its checks in an `Rc`, so it is neither `Send` nor `Sync`
+    struct CountedEquals(u64, Rc<Cell<u64>>);
+
+    impl Requirement for CountedEquals {
+        fn description(&self) -> String {
+            format!("== {}", self.0)
+        }
+
+        fn distance_from(&self, field: &Field) -> u64 {
+            self.1.set(self.1.get() + 1);
+            let value = field.as_u64().unwrap_or(0);
+            value.max(self.0) - value.min(self.0)
+        }
+
+        fn field_type(&self) -> Option<FieldType> {
+            Some(FieldType::U64)
+        }
+    }
+
+    #[test]
+    fn plain_goals_take_requirements_that_are_not_thread_safe() {
+        let checks = Rc::new(Cell::new(0));
+        let goal = Goal::new().with_req("x", Box::new(CountedEquals(2, checks.clone())));
+        let start = State::new().with_field("x", Field::from(0u64));
+        let actions: Vec<Box<dyn Action>> = vec![Box::new(Add(1))];
+        let plan = plan_with(&start, &actions, &goal, &PlanOptions::new()).unwrap();
+        assert_eq!(plan.1, 2);
+        assert!(checks.get() > 0);
+    }
+
+    #[test]
+    fn jobs_plan_towards_shared_goals() {
+        let actions: Vec<SyncAction> = vec![Box::new(Add(1))];
+        let jobs: Vec<PlanJob> = (0..4u64)
+            .map(|target| {
+                PlanJob::new(
+                    State::new().with_field("x", Field::from(0u64)),
+                    SyncGoal::new().with_req(
+                        "x",
+                        Box::new(CompareRequirement::Equals(Field::from(target))),
+                    ),
+                )
+            })
+            .collect();
+        let costs: Vec<Option<u64>> = plan_many(&actions, &jobs)
+            .into_iter()
+            .map(|plan| plan.map(|(_, cost)| cost))
+            .collect();
+        assert_eq!(costs, vec![Some(0), Some(1), Some(2), Some(3)]);
+    }
+}
@@ -0,0 +1,316 @@
+use std::collections::BTreeSet;
+
+use crate::{
+    action::{Action, Consequence},
+    planner::{prepare_with, Node, Plan, PlanOptions},
+    state::State,
+    trace::escape,
+    validation::replay_step,
+};
+
+#[derive(Clone, Debug)]
+pub struct PartialStep {
+    // Index of the step's node in the plan
+    pub step: usize,
+    pub consequence: Consequence,
+    pub cost: u64,
+    // `None` when the action declares no reads, the step then stays
+    // ordered with every other step
+    pub reads: Option<BTreeSet<String>>,
+    pub writes: BTreeSet<String>,
+}
+
+impl PartialStep {
+    // Whether the two steps have to keep their order
+    fn conflicts_with(&self, other: &PartialStep) -> bool {
+        match (&self.reads, &other.reads) {
+            (Some(reads), Some(other_reads)) => {
+                !self.writes.is_disjoint(other_reads)
+                    || !self.writes.is_disjoint(&other.writes)
+                    || !reads.is_disjoint(&other.writes)
+            }
+            _ => true,
+        }
+    }
+}
+
+// `from` writes `key` and is the last step to do so before `to` reads it
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct CausalLink {
+    pub from: usize,
+    pub to: usize,
+    pub key: String,
+}
+
+// Steps are referred to by their position in `steps`
+#[derive(Clone, Debug)]
+pub struct PartialOrderPlan {
+    steps: Vec<PartialStep>,
+    orderings: BTreeSet<(usize, usize)>,
+    causal_links: Vec<CausalLink>,
+}
+
+impl PartialOrderPlan {
+    pub fn steps(&self) -> &[PartialStep] {
+        &self.steps
+    }
+
+    // (before, after) pairs, without the ones implied by transitivity
+    pub fn orderings(&self) -> &BTreeSet<(usize, usize)> {
+        &self.orderings
+    }
+
+    pub fn causal_links(&self) -> &[CausalLink] {
+        &self.causal_links
+    }
+
+    pub fn predecessors(&self, step: usize) -> Vec<usize> {
+        self.orderings
+            .iter()
+            .filter(|(_, after)| *after == step)
+            .map(|(before, _)| *before)
+            .collect()
+    }
+
+    pub fn successors(&self, step: usize) -> Vec<usize> {
+        self.orderings
+            .iter()
+            .filter(|(before, _)| *before == step)
+            .map(|(_, after)| *after)
+            .collect()
+    }
+
+    pub fn is_before(&self, before: usize, after: usize) -> bool {
+        let mut stack = self.successors(before);
+        let mut seen = BTreeSet::new();
+        while let Some(step) = stack.pop() {
+            if step == after {
+                return true;
+            }
+            if seen.insert(step) {
+                stack.extend(self.successors(step));
+            }
+        }
+        false
+    }
+
+    // Groups of steps that can run at the same time, each group only
+    // depending on earlier ones
+    pub fn layers(&self) -> Vec<Vec<usize>> {
+        let mut depth = vec![0; self.steps.len()];
+        // Orderings always point forward in the original plan
+        for step in 0..self.steps.len() {
+            for before in self.predecessors(step) {
+                depth[step] = depth[step].max(depth[before] + 1);
+            }
+        }
+        let mut layers: Vec<Vec<usize>> = vec![];
+        for (step, depth) in depth.into_iter().enumerate() {
+            if layers.len() <= depth {
+                layers.resize(depth + 1, vec![]);
+            }
+            layers[depth].push(step);
+        }
+        layers
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph plan {\n    node [shape=box];\n");
+        for (index, step) in self.steps.iter().enumerate() {
+            dot.push_str(&format!(
+                "    s{} [label=\"{}\"];\n",
+                index,
+                escape(&step.consequence.label())
+            ));
+        }
+        for (before, after) in &self.orderings {
+            let keys: Vec<&str> = self
+                .causal_links
+                .iter()
+                .filter(|link| link.from == *before && link.to == *after)
+                .map(|link| link.key.as_str())
+                .collect();
+            if keys.is_empty() {
+                dot.push_str(&format!("    s{} -> s{} [style=dashed];\n", before, after));
+            } else {
+                dot.push_str(&format!(
+                    "    s{} -> s{} [label=\"{}\"];\n",
+                    before,
+                    after,
+                    keys.join(", ")
+                ));
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+// Keeps an ordering between two steps only when one writes a key the other
+// reads or writes. Writes are the keys a step changes plus the declared
+// `Action::writes`, reads are the declared `Action::reads`. Steps of
+// actions that declare no reads may depend on anything and keep their
+// place in the plan. Returns `None` if a step can no longer be replayed
+// with the given actions
+pub fn deorder<'a>(
+    start: &State,
+    actions: &[Box<dyn Action + 'a>],
+    plan: &Plan,
+) -> Option<PartialOrderPlan> {
+    deorder_with(start, actions, plan, &PlanOptions::new())
+}
+
+// Replays from the start prepared with the schema of the options, like the
+// plan was planned
+pub fn deorder_with<'a>(
+    start: &State,
+    actions: &[Box<dyn Action + 'a>],
+    plan: &Plan,
+    options: &PlanOptions,
+) -> Option<PartialOrderPlan> {
+    let mut state = prepare_with(start, actions, options);
+    let mut steps: Vec<PartialStep> = vec![];
+    for (index, node) in plan.0.iter().enumerate().skip(1) {
+        let consequence = match node {
+            Node::Consequence(consequence) => consequence,
+            Node::State(_) => return None,
+        };
+        let action = actions
+            .iter()
+            .find(|action| action.key() == consequence.action)?;
+        let cost = replay_step(&state, actions, consequence).ok()?;
+        let (reads, writes) = step_accesses(&**action, &state, &consequence.result);
+        steps.push(PartialStep {
+            step: index,
+            consequence: consequence.clone(),
+            cost,
+            reads,
+            writes,
+        });
+        state = consequence.result.clone();
+    }
+
+    let mut causal_links = vec![];
+    let mut orderings = BTreeSet::new();
+    for (after, step) in steps.iter().enumerate() {
+        for key in step.reads.iter().flatten() {
+            if let Some(before) = (0..after)
+                .rev()
+                .find(|&before| steps[before].writes.contains(key))
+            {
+                causal_links.push(CausalLink {
+                    from: before,
+                    to: after,
+                    key: key.clone(),
+                });
+            }
+        }
+        for (before, previous) in steps[..after].iter().enumerate() {
+            if previous.conflicts_with(step) {
+                orderings.insert((before, after));
+            }
+        }
+    }
+
+    let mut plan = PartialOrderPlan {
+        steps,
+        orderings,
+        causal_links,
+    };
+    // Transitive reduction, an ordering is redundant when another path
+    // already implies it
+    let all: Vec<(usize, usize)> = plan.orderings.iter().cloned().collect();
+    for (before, after) in all {
+        plan.orderings.remove(&(before, after));
+        if !plan.is_before(before, after) {
+            plan.orderings.insert((before, after));
+        }
+    }
+    Some(plan)
+}
+
+pub(crate) fn step_accesses(
+    action: &dyn Action,
+    before: &State,
+    after: &State,
+) -> (Option<BTreeSet<String>>, BTreeSet<String>) {
+    let reads: BTreeSet<String> = action.reads().into_iter().collect();
+    let reads = if reads.is_empty() { None } else { Some(reads) };
+    let mut writes: BTreeSet<String> = action.writes().into_iter().collect();
+    for (key, value) in after.iter() {
+        if before.get(key).as_ref() != Some(value) {
+            writes.insert(key.clone());
+        }
+    }
+    for (key, _) in before.iter() {
+        if !after.contains_key(key) {
+            writes.insert(key.clone());
+        }
+    }
+    (reads, writes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::field::Field;
+
+    // Sets its key to true, reading the keys it declares
+    struct Set(&'static str, Vec<&'static str>);
+
+    impl Action for Set {
+        fn key(&self) -> String {
+            self.0.to_owned()
+        }
+
+        fn options(&self, state: &State) -> Vec<(Consequence, u64)> {
+            vec![(
+                Consequence {
+                    action: self.key(),
+                    argument: None,
+                    result: state.with_field(self.0, Field::from(true)),
+                },
+                1,
+            )]
+        }
+
+        fn reads(&self) -> Vec<String> {
+            self.1.iter().map(|key| key.to_string()).collect()
+        }
+    }
+
+    fn plan_of(start: &State, actions: &[Box<dyn Action>]) -> Plan {
+        let mut nodes = vec![Node::State(start.clone())];
+        let mut state = start.clone();
+        for action in actions {
+            let (consequence, _) = action.options(&state).remove(0);
+            state = consequence.result.clone();
+            nodes.push(Node::Consequence(consequence));
+        }
+        (nodes, actions.len() as u64)
+    }
+
+    #[test]
+    fn steps_without_declared_reads_keep_their_order() {
+        let start = State::new();
+        let actions: Vec<Box<dyn Action>> = vec![
+            Box::new(Set("shrooms", vec!["shrooms"])),
+            Box::new(Set("axe", vec!["axe"])),
+            Box::new(Set("wood", vec![])),
+        ];
+        let partial = deorder(&start, &actions, &plan_of(&start, &actions)).unwrap();
+        assert!(partial.is_before(1, 2));
+        assert!(partial.is_before(0, 2));
+        assert_eq!(partial.layers(), vec![vec![0, 1], vec![2]]);
+
+        let declared: Vec<Box<dyn Action>> = vec![
+            Box::new(Set("shrooms", vec!["shrooms"])),
+            Box::new(Set("axe", vec!["axe"])),
+            Box::new(Set("wood", vec!["axe"])),
+        ];
+        let partial = deorder(&start, &declared, &plan_of(&start, &declared)).unwrap();
+        assert!(partial.is_before(1, 2));
+        assert!(!partial.is_before(0, 2));
+        assert_eq!(partial.causal_links().len(), 1);
+    }
+}
@@ -1,7 +1,18 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
 use crate::{
     action::{Action, Consequence},
+    constraint::{ConstraintSet, Violation},
     goal::Goal,
-    state::State,
+    schema::Schema,
+    state::{State, StateKey},
+    stats::SearchStats,
+    trace::SearchTrace,
 };
 
 #[derive(Clone, Eq, Hash, PartialEq)]
@@ -28,42 +39,655 @@ impl std::fmt::Debug for Node {
     }
 }
 
-pub fn plan<'a>(
+pub type Plan = (Vec<Node>, u64);
+
+#[derive(Clone, Debug)]
+pub struct PlanOptions {
+    seed: Option<u64>,
+    max_expansions: Option<u64>,
+    time_limit: Option<Duration>,
+    batch_size: usize,
+    constraints: ConstraintSet,
+    schema: Option<Arc<Schema>>,
+    // Bounds of the schema's numeric keys
+    bounds: ConstraintSet,
+}
+
+impl Default for PlanOptions {
+    fn default() -> Self {
+        PlanOptions::new()
+    }
+}
+
+impl PlanOptions {
+    pub fn new() -> Self {
+        PlanOptions {
+            seed: None,
+            max_expansions: None,
+            time_limit: None,
+            batch_size: 1,
+            constraints: ConstraintSet::new(),
+            schema: None,
+            bounds: ConstraintSet::new(),
+        }
+    }
+
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    pub fn max_expansions(&self) -> Option<u64> {
+        self.max_expansions
+    }
+
+    pub fn time_limit(&self) -> Option<Duration> {
+        self.time_limit
+    }
+
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    pub fn constraints(&self) -> &ConstraintSet {
+        &self.constraints
+    }
+
+    pub fn schema(&self) -> Option<&Schema> {
+        self.schema.as_deref()
+    }
+
+    // Equal-cost nodes are expanded in a seeded random order instead of
+    // the order in which they were generated
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    // Give up once this many nodes were expanded without reaching the goal
+    pub fn with_max_expansions(mut self, max_expansions: u64) -> Self {
+        self.max_expansions = Some(max_expansions);
+        self
+    }
+
+    // Give up once the search ran for this long without reaching the goal
+    pub fn with_time_limit(mut self, time_limit: Duration) -> Self {
+        self.time_limit = Some(time_limit);
+        self
+    }
+
+    // Number of open nodes expanded together, only worth raising when the
+    // expansion runs in parallel
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    // Consequences whose result violates one of the constraints are never
+    // added to the search, the start state is not checked
+    pub fn with_constraints(mut self, constraints: ConstraintSet) -> Self {
+        self.constraints = constraints;
+        self
+    }
+
+    // The schema's defaults are filled in before the actions prepare the
+    // start state and its bounds are kept like constraints. Goals that
+    // don't fit the schema are not planned for
+    pub fn with_schema(mut self, schema: Schema) -> Self {
+        self.bounds = schema.constraints();
+        self.schema = Some(Arc::new(schema));
+        self
+    }
+
+    // The first constraint or schema bound the state violates
+    pub fn check(&self, state: &State) -> Option<Violation> {
+        self.constraints
+            .check(state)
+            .or_else(|| self.bounds.check(state))
+    }
+
+    pub(crate) fn fits_schema(&self, goal: &Goal) -> bool {
+        let errors = match &self.schema {
+            Some(schema) => schema.validate_goal(goal),
+            None => return true,
+        };
+        if !errors.is_empty() {
+            debug!("----- Goal doesn't fit the schema: {:?} -----", errors);
+        }
+        errors.is_empty()
+    }
+
+    pub(crate) fn budget_exhausted(&self, expanded: u64, started: Instant) -> bool {
+        matches!(self.max_expansions, Some(max) if expanded >= max)
+            || matches!(self.time_limit, Some(limit) if started.elapsed() >= limit)
+    }
+}
+
+pub fn plan<'a>(start: &State, actions: &[Box<dyn Action + 'a>], goal: &Goal) -> Option<Plan> {
+    plan_with(start, actions, goal, &PlanOptions::new())
+}
+
+pub fn plan_with<'a>(
     start: &State,
     actions: &[Box<dyn Action + 'a>],
     goal: &Goal,
-) -> Option<(Vec<Node>, u64)> {
-    // Prepare the state
-    let mut start = start.clone();
+    options: &PlanOptions,
+) -> Option<Plan> {
+    plan_with_stats(start, actions, goal, options).0
+}
+
+pub fn plan_with_stats<'a>(
+    start: &State,
+    actions: &[Box<dyn Action + 'a>],
+    goal: &Goal,
+    options: &PlanOptions,
+) -> (Option<Plan>, SearchStats) {
+    let mut stats = SearchStats::new();
+    let plan = search(start, actions, goal, options, &mut stats, None);
+    (plan, stats)
+}
+
+pub fn plan_with_trace<'a>(
+    start: &State,
+    actions: &[Box<dyn Action + 'a>],
+    goal: &Goal,
+    options: &PlanOptions,
+) -> (Option<Plan>, SearchTrace) {
+    let mut stats = SearchStats::new();
+    let mut trace = SearchTrace::new();
+    let plan = search(start, actions, goal, options, &mut stats, Some(&mut trace));
+    (plan, trace)
+}
+
+pub fn prepare<'a>(start: &State, actions: &[Box<dyn Action + 'a>]) -> State {
+    let mut state = start.clone();
     for action in actions {
-        start = action.prepare(&start);
+        state = action.prepare(&state);
+    }
+    state
+}
+
+// Fills in the schema of the options, if any, then prepares the actions
+pub fn prepare_with<'a>(
+    start: &State,
+    actions: &[Box<dyn Action + 'a>],
+    options: &PlanOptions,
+) -> State {
+    prepare_with_schema(start, actions, options.schema())
+}
+
+// Like `prepare_with`, `None` when the start doesn't fit the schema of the
+// options even with its defaults filled in
+pub(crate) fn prepare_checked<'a>(
+    start: &State,
+    actions: &[Box<dyn Action + 'a>],
+    options: &PlanOptions,
+) -> Option<State> {
+    let start = match options.schema().map(|schema| schema.prepare(start)) {
+        Some(Ok(start)) => start,
+        Some(Err(errors)) => {
+            debug!("----- Start doesn't fit the schema: {:?} -----", errors);
+            return None;
+        }
+        None => start.clone(),
+    };
+    Some(prepare(&start, actions))
+}
+
+pub fn prepare_with_schema<'a>(
+    start: &State,
+    actions: &[Box<dyn Action + 'a>],
+    schema: Option<&Schema>,
+) -> State {
+    match schema {
+        Some(schema) => prepare(&schema.fill_defaults(start), actions),
+        None => prepare(start, actions),
+    }
+}
+
+fn search<'a>(
+    start: &State,
+    actions: &[Box<dyn Action + 'a>],
+    goal: &Goal,
+    options: &PlanOptions,
+    stats: &mut SearchStats,
+    trace: Option<&mut SearchTrace>,
+) -> Option<Plan> {
+    if !options.fits_schema(goal) {
+        return None;
     }
+    // Prepare the state
+    let prepare_start = Instant::now();
+    let start = prepare_checked(start, actions, options)?;
+    stats.prepare_time += prepare_start.elapsed();
     // Plan
-    let start_node = Node::State(start.clone());
-    let result = pathfinding::prelude::astar(
-        &start_node,
-        |node| consequences(node, actions),
-        |node| {
-            debug!("----- Heuristic -----");
-            debug!("To node: {:?}", node);
-            node.state().distance_to_goal(goal)
-        },
-        |node| {
-            debug!("-------------------");
-            debug!("----- Success -----");
-            debug!("-------------------");
-            debug!("From node: {:?}", node);
-            node.state().distance_to_goal(goal) == 0
-        },
-    );
-
-    result
-}
-
-fn consequences<'a>(node: &Node, actions: &[Box<dyn Action + 'a>]) -> Vec<(Node, u64)> {
-    actions
+    astar(
+        start,
+        &|batch, stats| expand(batch, actions, stats),
+        &Target::goal(goal),
+        options,
+        stats,
+        trace,
+    )
+}
+
+pub(crate) type Expander<'e> = dyn Fn(&[&Node], &mut SearchStats) -> Vec<Vec<(Node, u64)>> + 'e;
+
+// Whether a state reached at a cost can still lead to a success
+pub(crate) type Viable<'t> = dyn Fn(&State, u64) -> bool + 't;
+
+// What the search is looking for
+pub(crate) struct Target<'t> {
+    pub estimate: Box<dyn Fn(&State) -> u64 + 't>,
+    pub is_success: Box<dyn Fn(&State) -> bool + 't>,
+    pub is_viable: Box<Viable<'t>>,
+}
+
+impl<'t> Target<'t> {
+    pub(crate) fn goal(goal: &'t Goal) -> Self {
+        Target {
+            estimate: Box::new(move |state| state.distance_to_goal(goal)),
+            is_success: Box::new(move |state| state.distance_to_goal(goal) == 0),
+            is_viable: Box::new(move |state, cost| goal.can_meet_deadlines(state, cost)),
+        }
+    }
+}
+
+pub(crate) fn astar(
+    start: State,
+    expander: &Expander,
+    target: &Target,
+    options: &PlanOptions,
+    stats: &mut SearchStats,
+    mut trace: Option<&mut SearchTrace>,
+) -> Option<Plan> {
+    let Target {
+        estimate,
+        is_success,
+        is_viable,
+    } = target;
+    let started = Instant::now();
+    let mut expanded = 0;
+    let mut tie_breaker = TieBreaker::new(options.seed);
+    if !is_viable(&start, 0) {
+        debug!("----- Deadline can't be met -----");
+        return None;
+    }
+    let start_node = Node::State(start);
+    let start_estimate = heuristic(&start_node, estimate, stats);
+
+    let mut nodes: Vec<SearchNode> = vec![SearchNode {
+        node: start_node.clone(),
+        parent: None,
+        cost: 0,
+        estimate: start_estimate,
+    }];
+    // `Node` can't be the key, its hash covers the action while its
+    // equality does not
+    let mut indices: HashMap<StateKey, usize> = HashMap::new();
+    indices.insert(StateKey::new(start_node.state()), 0);
+    let mut open = BinaryHeap::new();
+    open.push(OpenEntry {
+        estimated_cost: start_estimate,
+        cost: 0,
+        tie: tie_breaker.next(),
+        index: 0,
+    });
+    stats.nodes_generated += 1;
+    stats.max_open_set = 1;
+
+    loop {
+        // Take the best open nodes, stopping at the first goal
+        let mut batch: Vec<usize> = vec![];
+        while batch.len() < options.batch_size {
+            let entry = match open.pop() {
+                Some(entry) => entry,
+                None => break,
+            };
+            // Skip entries that were superseded by a cheaper path
+            if entry.cost > nodes[entry.index].cost {
+                continue;
+            }
+            let goal_reached = success(&nodes[entry.index].node, is_success);
+            if goal_reached && !batch.is_empty() {
+                // Nodes before it in the batch may still lead to a cheaper goal
+                open.push(entry);
+                break;
+            }
+            if let Some(trace) = trace.as_deref_mut() {
+                let current = &nodes[entry.index];
+                trace.record(
+                    entry.index,
+                    current.parent,
+                    &current.node,
+                    current.cost,
+                    current.estimate,
+                    goal_reached,
+                );
+            }
+            if goal_reached {
+                let path = path_indices(&nodes, entry.index);
+                let plan = path
+                    .iter()
+                    .map(|&index| nodes[index].node.clone())
+                    .collect();
+                if let Some(trace) = trace {
+                    trace.set_plan(path);
+                }
+                return Some((plan, entry.cost));
+            }
+            batch.push(entry.index);
+        }
+        if batch.is_empty() {
+            break;
+        }
+
+        if options.budget_exhausted(expanded, started) {
+            debug!("----- Search budget exhausted -----");
+            break;
+        }
+        expanded += batch.len() as u64;
+        stats.nodes_expanded += batch.len() as u64;
+        let batch_nodes: Vec<&Node> = batch.iter().map(|&index| &nodes[index].node).collect();
+        let successors = expander(&batch_nodes, stats);
+
+        for (parent, successors) in batch.into_iter().zip(successors) {
+            // The parent may have been reached more cheaply by an earlier
+            // node of the same batch
+            let parent_cost = nodes[parent].cost;
+            for (successor, move_cost) in successors {
+                let cost = parent_cost + move_cost;
+                stats.nodes_generated += 1;
+                if let Some(violation) = options.check(successor.state()) {
+                    debug!("Pruned: {}", violation.description());
+                    *stats
+                        .constraint_violations
+                        .entry(violation.constraint.clone())
+                        .or_insert(0) += 1;
+                    if let Some(trace) = trace.as_deref_mut() {
+                        trace.record_pruned(parent, &successor, violation);
+                    }
+                    continue;
+                }
+                if !is_viable(successor.state(), cost) {
+                    stats.deadline_pruned += 1;
+                    continue;
+                }
+                let key = StateKey::new(successor.state());
+                let index = match indices.get(&key) {
+                    Some(&index) => {
+                        stats.duplicate_hits += 1;
+                        if nodes[index].cost <= cost {
+                            continue;
+                        }
+                        // The cheaper path may reach the state by another action
+                        nodes[index].node = successor;
+                        nodes[index].parent = Some(parent);
+                        nodes[index].cost = cost;
+                        index
+                    }
+                    None => {
+                        let index = nodes.len();
+                        let estimate = heuristic(&successor, estimate, stats);
+                        indices.insert(key, index);
+                        nodes.push(SearchNode {
+                            node: successor,
+                            parent: Some(parent),
+                            cost,
+                            estimate,
+                        });
+                        index
+                    }
+                };
+                open.push(OpenEntry {
+                    estimated_cost: cost + nodes[index].estimate,
+                    cost,
+                    tie: tie_breaker.next(),
+                    index,
+                });
+            }
+        }
+        stats.max_open_set = stats.max_open_set.max(open.len());
+    }
+
+    None
+}
+
+fn heuristic(node: &Node, estimate: &dyn Fn(&State) -> u64, stats: &mut SearchStats) -> u64 {
+    debug!("----- Heuristic -----");
+    debug!("To node: {:?}", node);
+    let heuristic_start = Instant::now();
+    let estimate = estimate(node.state());
+    stats.heuristic_time += heuristic_start.elapsed();
+    stats.heuristic_evaluations += 1;
+    estimate
+}
+
+fn success(node: &Node, is_success: &dyn Fn(&State) -> bool) -> bool {
+    debug!("-------------------");
+    debug!("----- Success -----");
+    debug!("-------------------");
+    debug!("From node: {:?}", node);
+    is_success(node.state())
+}
+
+pub(crate) fn expand<'a>(
+    batch: &[&Node],
+    actions: &[Box<dyn Action + 'a>],
+    stats: &mut SearchStats,
+) -> Vec<Vec<(Node, u64)>> {
+    batch
         .iter()
-        .flat_map(|action| action.options(node.state()))
-        .map(|(consequence, cost)| (Node::Consequence(consequence), cost))
+        .map(|node| consequences(node, actions, stats))
         .collect()
 }
+
+fn consequences<'a>(
+    node: &Node,
+    actions: &[Box<dyn Action + 'a>],
+    stats: &mut SearchStats,
+) -> Vec<(Node, u64)> {
+    let mut consequences = vec![];
+    for action in actions {
+        let options_start = Instant::now();
+        let options = action.options(node.state());
+        stats.options_time += options_start.elapsed();
+        *stats.action_expansions.entry(action.key()).or_insert(0) += options.len() as u64;
+        consequences.extend(
+            options
+                .into_iter()
+                .map(|(consequence, cost)| (Node::Consequence(consequence), cost)),
+        );
+    }
+    consequences
+}
+
+fn path_indices(nodes: &[SearchNode], mut index: usize) -> Vec<usize> {
+    let mut path = vec![index];
+    while let Some(parent) = nodes[index].parent {
+        path.push(parent);
+        index = parent;
+    }
+    path.reverse();
+    path
+}
+
+struct SearchNode {
+    node: Node,
+    parent: Option<usize>,
+    cost: u64,
+    estimate: u64,
+}
+
+// Without a seed ties are broken in generation order, which only depends
+// on the order of the actions and their options
+pub(crate) struct TieBreaker {
+    rng: Option<StdRng>,
+    counter: u64,
+}
+
+impl TieBreaker {
+    pub(crate) fn new(seed: Option<u64>) -> Self {
+        TieBreaker {
+            rng: seed.map(StdRng::seed_from_u64),
+            counter: 0,
+        }
+    }
+
+    pub(crate) fn next(&mut self) -> u64 {
+        match &mut self.rng {
+            Some(rng) => rng.gen(),
+            None => {
+                self.counter += 1;
+                self.counter
+            }
+        }
+    }
+}
+
+#[derive(Eq, PartialEq)]
+struct OpenEntry {
+    estimated_cost: u64,
+    cost: u64,
+    tie: u64,
+    index: usize,
+}
+
+impl Ord for OpenEntry {
+    // Lowest estimate first, then the deepest node, then the lowest tie key
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .estimated_cost
+            .cmp(&self.estimated_cost)
+            .then_with(|| self.cost.cmp(&other.cost))
+            .then_with(|| other.tie.cmp(&self.tie))
+    }
+}
+impl PartialOrd for OpenEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{field::Field, requirement::CompareRequirement};
+
+    // Adds one of the steps to its key at the step's cost. No step costs
+    // less than the distance it covers, so the heuristic stays admissible
+    struct Steps(&'static str, Vec<(u64, u64)>);
+
+    impl Action for Steps {
+        fn key(&self) -> String {
+            self.0.to_owned()
+        }
+
+        fn options(&self, state: &State) -> Vec<(Consequence, u64)> {
+            let value = state.get_as_u64(self.0).unwrap_or(0);
+            self.1
+                .iter()
+                .filter(|(step, _)| value + step <= 12)
+                .map(|&(step, cost)| {
+                    (
+                        Consequence {
+                            action: self.key(),
+                            argument: Some(step.into()),
+                            result: state.with_field(self.0, Field::from(value + step)),
+                        },
+                        cost,
+                    )
+                })
+                .collect()
+        }
+    }
+
+    fn equals(goal: Goal, key: &str, value: u64) -> Goal {
+        goal.with_req(
+            key,
+            Box::new(CompareRequirement::Equals(Field::from(value))),
+        )
+    }
+
+    fn steps(plan: &Plan) -> Vec<String> {
+        plan.0
+            .iter()
+            .filter_map(|node| match node {
+                Node::Consequence(consequence) => Some(format!(
+                    "{}+{}",
+                    consequence.action,
+                    consequence.argument.as_ref()?
+                )),
+                Node::State(_) => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn equal_inputs_and_seed_give_equal_plans() {
+        let start = State::new();
+        let actions: Vec<Box<dyn Action>> = vec![
+            Box::new(Steps("x", vec![(1, 1), (2, 2)])),
+            Box::new(Steps("y", vec![(1, 1), (2, 2)])),
+        ];
+        let goal = equals(equals(Goal::new(), "x", 3), "y", 3);
+        for options in &[PlanOptions::new(), PlanOptions::new().with_seed(7)] {
+            let first = plan_with(&start, &actions, &goal, options).unwrap();
+            let second = plan_with(&start, &actions, &goal, options).unwrap();
+            assert_eq!(steps(&first), steps(&second));
+            assert_eq!(first.1, second.1);
+        }
+    }
+
+    #[test]
+    fn seeds_change_the_order_of_ties() {
+        // Every interleaving of the four steps costs 4
+        let start = State::new();
+        let actions: Vec<Box<dyn Action>> = vec![
+            Box::new(Steps("x", vec![(1, 1)])),
+            Box::new(Steps("y", vec![(1, 1)])),
+        ];
+        let goal = equals(equals(Goal::new(), "x", 2), "y", 2);
+        let plans: Vec<Vec<String>> = (0..16)
+            .map(|seed| {
+                let options = PlanOptions::new().with_seed(seed);
+                let plan = plan_with(&start, &actions, &goal, &options).unwrap();
+                assert_eq!(plan.1, 4);
+                steps(&plan)
+            })
+            .collect();
+        assert!(plans.iter().any(|plan| plan != &plans[0]));
+
+        let unseeded = plan_with(&start, &actions, &goal, &PlanOptions::new()).unwrap();
+        assert_eq!(steps(&unseeded), vec!["x+1", "x+1", "y+1", "y+1"]);
+    }
+
+    #[test]
+    fn finds_the_cheapest_plan() {
+        // The big steps are tempting but overpriced, 5 + 2 costs 10
+        let start = State::new().with_field("x", Field::from(0u64));
+        let actions: Vec<Box<dyn Action>> =
+            vec![Box::new(Steps("x", vec![(1, 1), (2, 3), (5, 7)]))];
+        let goal = equals(Goal::new(), "x", 7);
+        let plan = plan_with(&start, &actions, &goal, &PlanOptions::new()).unwrap();
+        assert_eq!(plan.1, 7);
+        assert_eq!(plan.0.len(), 8);
+        assert_eq!(plan.0.last().unwrap().state().get_as_u64("x"), Some(7));
+
+        // Uniform cost search agrees
+        let mut stats = SearchStats::new();
+        let target = Target {
+            estimate: Box::new(|_| 0),
+            ..Target::goal(&goal)
+        };
+        let uniform = astar(
+            start,
+            &|batch, stats| expand(batch, &actions, stats),
+            &target,
+            &PlanOptions::new(),
+            &mut stats,
+            None,
+        )
+        .unwrap();
+        assert_eq!(uniform.1, plan.1);
+    }
+}
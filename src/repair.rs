@@ -0,0 +1,150 @@
+use crate::{
+    action::Action,
+    goal::Goal,
+    planner::{astar, expand, plan_with, prepare_checked, Node, Plan, PlanOptions, Target},
+    state::State,
+    stats::SearchStats,
+    validation::replay_step,
+};
+
+#[derive(Clone, Debug)]
+pub enum Repair {
+    // A sub-plan from the observed state rejoined the old plan at the node
+    // with this index and kept the rest of it, or reached the goal itself
+    Spliced {
+        plan: Plan,
+        rejoined_at: Option<usize>,
+    },
+    // The observed state already satisfies the goal
+    Satisfied(Plan),
+    // No splice was found, so the goal was planned for from scratch
+    Replanned(Plan),
+}
+
+impl Repair {
+    pub fn plan(&self) -> &Plan {
+        match self {
+            Repair::Spliced { plan, .. } => plan,
+            Repair::Satisfied(plan) => plan,
+            Repair::Replanned(plan) => plan,
+        }
+    }
+
+    pub fn into_plan(self) -> Plan {
+        match self {
+            Repair::Spliced { plan, .. } => plan,
+            Repair::Satisfied(plan) => plan,
+            Repair::Replanned(plan) => plan,
+        }
+    }
+}
+
+// `diverged_at` is the index of the first node in `old` whose state was
+// not observed. The splice search is limited to `max_splice_expansions`
+// nodes, `options` are used for both searches
+pub fn repair<'a>(
+    old: &Plan,
+    diverged_at: usize,
+    observed: &State,
+    actions: &[Box<dyn Action + 'a>],
+    goal: &Goal,
+    options: &PlanOptions,
+    max_splice_expansions: u64,
+) -> Option<Repair> {
+    if !options.fits_schema(goal) {
+        return None;
+    }
+    let observed = prepare_checked(observed, actions, options)?;
+    if observed.distance_to_goal(goal) == 0 {
+        return Some(Repair::Satisfied((vec![Node::State(observed)], 0)));
+    }
+
+    let (old_nodes, _) = old;
+    let targets: Vec<(usize, &State)> = old_nodes
+        .iter()
+        .enumerate()
+        .skip(diverged_at)
+        .map(|(index, node)| (index, node.state()))
+        .collect();
+
+    if !targets.is_empty() {
+        let splice_options = options.clone().with_max_expansions(max_splice_expansions);
+        let mut stats = SearchStats::new();
+        let splice_target = Target {
+            estimate: Box::new(|state| {
+                targets
+                    .iter()
+                    .map(|(_, target)| state.distance_to(target))
+                    .min()
+                    .unwrap_or(0)
+            }),
+            is_success: Box::new(|state| {
+                state.distance_to_goal(goal) == 0
+                    || targets
+                        .iter()
+                        .any(|(_, target)| state.distance_to(target) == 0)
+            }),
+            ..Target::goal(goal)
+        };
+        let sub_plan = astar(
+            observed.clone(),
+            &|batch, stats| expand(batch, actions, stats),
+            &splice_target,
+            &splice_options,
+            &mut stats,
+            None,
+        );
+        if let Some(spliced) =
+            sub_plan.and_then(|sub_plan| splice(sub_plan, &targets, old, actions, goal))
+        {
+            return Some(spliced);
+        }
+    }
+
+    debug!("----- Splice failed, replanning -----");
+    plan_with(&observed, actions, goal, options).map(Repair::Replanned)
+}
+
+fn splice<'a>(
+    (mut nodes, mut cost): Plan,
+    targets: &[(usize, &State)],
+    old: &Plan,
+    actions: &[Box<dyn Action + 'a>],
+    goal: &Goal,
+) -> Option<Repair> {
+    let last = nodes.last()?.state().clone();
+    // Rejoin as late as possible to skip the most work
+    let rejoined_at = match targets
+        .iter()
+        .rev()
+        .find(|(_, target)| last.distance_to(target) == 0)
+    {
+        Some((index, _)) => *index,
+        None => {
+            return Some(Repair::Spliced {
+                plan: (nodes, cost),
+                rejoined_at: None,
+            })
+        }
+    };
+
+    // The remaining steps must still be offered from the rejoined state
+    let mut state = last;
+    for node in &old.0[rejoined_at + 1..] {
+        let consequence = match node {
+            Node::Consequence(consequence) => consequence,
+            Node::State(_) => return None,
+        };
+        cost += replay_step(&state, actions, consequence).ok()?;
+        state = consequence.result.clone();
+        nodes.push(node.clone());
+    }
+    if state.distance_to_goal(goal) != 0 {
+        return None;
+    }
+
+    Some(Repair::Spliced {
+        plan: (nodes, cost),
+        rejoined_at: Some(rejoined_at),
+    })
+}
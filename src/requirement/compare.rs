@@ -1,6 +1,6 @@
 use crate::{
     distance::{distance_eq, distance_f64, distance_i64, distance_u64},
-    field::Field,
+    field::{Field, FieldType},
 };
 
 use super::Requirement;
@@ -13,6 +13,18 @@ pub enum CompareRequirement {
     LessThanEquals(Field),
 }
 
+impl CompareRequirement {
+    pub fn field(&self) -> &Field {
+        match self {
+            CompareRequirement::Equals(field) => field,
+            CompareRequirement::MoreThan(field) => field,
+            CompareRequirement::MoreThanEquals(field) => field,
+            CompareRequirement::LessThan(field) => field,
+            CompareRequirement::LessThanEquals(field) => field,
+        }
+    }
+}
+
 impl Requirement for CompareRequirement {
     fn description(&self) -> String {
         match self {
@@ -24,14 +36,12 @@ impl Requirement for CompareRequirement {
         }
     }
 
+    fn field_type(&self) -> Option<FieldType> {
+        Some(self.field().field_type())
+    }
+
     fn distance_from(&self, other: &Field) -> u64 {
-        let this = match self {
-            CompareRequirement::Equals(field) => field,
-            CompareRequirement::MoreThan(field) => field,
-            CompareRequirement::MoreThanEquals(field) => field,
-            CompareRequirement::LessThan(field) => field,
-            CompareRequirement::LessThanEquals(field) => field,
-        };
+        let this = self.field();
 
         // JSON value
         if let Field::Value(_this) = this {
@@ -1,4 +1,6 @@
-use crate::field::Field;
+use std::sync::Arc;
+
+use crate::field::{Field, FieldType};
 
 pub use compare::*;
 
@@ -6,9 +8,26 @@ pub mod compare;
 
 pub type BoxedRequirement = Box<dyn Requirement>;
 
+pub type SyncRequirement = Box<dyn Requirement + Send + Sync>;
+
 pub trait Requirement {
     fn description(&self) -> String;
     fn distance_from(&self, field: &Field) -> u64;
+    fn field_type(&self) -> Option<FieldType> {
+        None
+    }
+}
+
+impl<R: Requirement + ?Sized> Requirement for Arc<R> {
+    fn description(&self) -> String {
+        (**self).description()
+    }
+    fn distance_from(&self, field: &Field) -> u64 {
+        (**self).distance_from(field)
+    }
+    fn field_type(&self) -> Option<FieldType> {
+        (**self).field_type()
+    }
 }
 
 impl std::fmt::Debug for dyn Requirement {
@@ -16,3 +35,9 @@ impl std::fmt::Debug for dyn Requirement {
         self.description().fmt(f)
     }
 }
+
+impl std::fmt::Debug for dyn Requirement + Send + Sync {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.description().fmt(f)
+    }
+}
@@ -0,0 +1,426 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use crate::{
+    action::Action,
+    constraint::ConstraintSet,
+    field::Field,
+    goal::Goal,
+    planner::{plan_with, prepare_checked, Plan, PlanOptions},
+    requirement::CompareRequirement,
+    state::State,
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Claim {
+    // Nobody else may change the key
+    Key(String),
+    // Part of a numeric key, such as 3 wood from the stockpile
+    Quantity { key: String, amount: u64 },
+}
+
+impl Claim {
+    pub fn key<S: AsRef<str>>(key: S) -> Self {
+        Claim::Key(key.as_ref().to_owned())
+    }
+
+    pub fn quantity<S: AsRef<str>>(key: S, amount: u64) -> Self {
+        Claim::Quantity {
+            key: key.as_ref().to_owned(),
+            amount,
+        }
+    }
+
+    pub fn key_name(&self) -> &str {
+        match self {
+            Claim::Key(key) => key,
+            Claim::Quantity { key, .. } => key,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ReservationConflict {
+    pub key: String,
+    // 1 for key claims
+    pub requested: u64,
+    pub available: u64,
+    pub held_by: Vec<String>,
+}
+
+#[derive(Clone, Debug)]
+struct Entry {
+    owner: String,
+    claims: Vec<Claim>,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    next_id: u64,
+    entries: BTreeMap<u64, Entry>,
+}
+
+// Claims on keys and quantities shared by plans made from the same world
+// state. Reservations of all owners count, so an owner replanning should
+// release its reservation first
+#[derive(Debug, Default)]
+pub struct ReservationTable {
+    entries: Mutex<Entries>,
+}
+
+impl ReservationTable {
+    pub fn new() -> Self {
+        ReservationTable::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Reserved amount of a quantity
+    pub fn reserved<S: AsRef<str>>(&self, key: S) -> u64 {
+        let entries = self.entries.lock().unwrap();
+        reserved_amount(&entries, key.as_ref())
+    }
+
+    pub fn holders<S: AsRef<str>>(&self, key: S) -> Vec<String> {
+        let entries = self.entries.lock().unwrap();
+        holders(&entries, key.as_ref())
+    }
+
+    // Takes all claims or none of them. A quantity can't be claimed beyond
+    // what `state` holds minus what is already reserved, and no claim can
+    // be made on a key someone holds with `Claim::Key`
+    pub fn reserve<S: AsRef<str>>(
+        &self,
+        state: &State,
+        owner: S,
+        claims: Vec<Claim>,
+    ) -> Result<Reservation<'_>, ReservationConflict> {
+        let mut entries = self.entries.lock().unwrap();
+        let mut requested: BTreeMap<&str, u64> = BTreeMap::new();
+        for claim in &claims {
+            let key = claim.key_name();
+            let locked = entries.entries.values().any(|entry| {
+                entry
+                    .claims
+                    .iter()
+                    .any(|other| matches!(other, Claim::Key(other) if other == key))
+            });
+            let conflict = match claim {
+                Claim::Key(_) => {
+                    let held = !holders(&entries, key).is_empty();
+                    if held {
+                        Some((1, 0))
+                    } else {
+                        None
+                    }
+                }
+                Claim::Quantity { amount, .. } => {
+                    let total = requested.entry(key).or_insert(0);
+                    *total += amount;
+                    let available = if locked {
+                        0
+                    } else {
+                        quantity(state, key).saturating_sub(reserved_amount(&entries, key))
+                    };
+                    if *total > available {
+                        Some((*total, available))
+                    } else {
+                        None
+                    }
+                }
+            };
+            if let Some((requested, available)) = conflict {
+                return Err(ReservationConflict {
+                    key: key.to_owned(),
+                    requested,
+                    available,
+                    held_by: holders(&entries, key),
+                });
+            }
+        }
+
+        let id = entries.next_id;
+        entries.next_id += 1;
+        entries.entries.insert(
+            id,
+            Entry {
+                owner: owner.as_ref().to_owned(),
+                claims: claims.clone(),
+            },
+        );
+        Ok(Reservation {
+            table: self,
+            id,
+            claims,
+        })
+    }
+
+    // Returns whether the reservation was still held
+    pub fn release(&self, id: u64) -> bool {
+        self.entries.lock().unwrap().entries.remove(&id).is_some()
+    }
+
+    // Releases every reservation of `owner`, returns how many there were
+    pub fn release_owner<S: AsRef<str>>(&self, owner: S) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.entries.len();
+        entries
+            .entries
+            .retain(|_, entry| entry.owner != owner.as_ref());
+        before - entries.entries.len()
+    }
+
+    // `state` with reserved amounts taken out of their quantities
+    pub fn view(&self, state: &State) -> State {
+        let entries = self.entries.lock().unwrap();
+        let mut view = state.clone();
+        let keys: Vec<String> = state.iter().map(|(key, _)| key.clone()).collect();
+        for key in keys {
+            let reserved = reserved_amount(&entries, &key);
+            if reserved == 0 {
+                continue;
+            }
+            match state.get(&key) {
+                Some(Field::U64(value)) => {
+                    view.insert(&key, Field::from(value.saturating_sub(reserved)));
+                }
+                Some(Field::I64(value)) => {
+                    view.insert(&key, Field::from(value - reserved as i64));
+                }
+                _ => {}
+            }
+        }
+        view
+    }
+
+    // Keys held with `Claim::Key` have to keep their value in `state`, and
+    // numeric keys can't drop below what is reserved of them
+    pub fn constraints(&self, state: &State) -> ConstraintSet {
+        let entries = self.entries.lock().unwrap();
+        let mut constraints = ConstraintSet::new();
+        for entry in entries.entries.values() {
+            for claim in &entry.claims {
+                if let Claim::Key(key) = claim {
+                    if let Some(value) = state.get(key) {
+                        constraints = constraints.with_constraint(
+                            format!("reserved by {}", entry.owner),
+                            key,
+                            Box::new(CompareRequirement::Equals(value)),
+                        );
+                    }
+                }
+            }
+        }
+        for (key, value) in state.iter() {
+            let reserved = reserved_amount(&entries, key);
+            if reserved == 0 {
+                continue;
+            }
+            // The lowest value `reserve` would still take a claim down to
+            let available = quantity(state, key).saturating_sub(reserved) as i128;
+            let lowest = match value {
+                Field::U64(value) => Field::from((*value as i128 - available) as u64),
+                Field::I64(value) => Field::from((*value as i128 - available) as i64),
+                _ => continue,
+            };
+            constraints = constraints.with_constraint(
+                format!("reserved by {}", holders(&entries, key).join(", ")),
+                key,
+                Box::new(CompareRequirement::MoreThanEquals(lowest)),
+            );
+        }
+        constraints
+    }
+
+    // Plans from `start` around the current reservations and reserves what
+    // the plan uses: the largest drop of every numeric key along the plan
+    // and every other key it changes. Replans if another owner reserved in
+    // between, up to `attempts` times
+    pub fn plan<'t, 'a, S: AsRef<str>>(
+        &'t self,
+        start: &State,
+        actions: &[Box<dyn Action + 'a>],
+        goal: &Goal,
+        options: &PlanOptions,
+        owner: S,
+        attempts: usize,
+    ) -> Option<(Plan, Reservation<'t>)> {
+        let start = prepare_checked(start, actions, options)?;
+        for _ in 0..attempts {
+            let options = options
+                .clone()
+                .with_constraints(self.constraints(&start).merged(options.constraints()));
+            let plan = plan_with(&start, actions, goal, &options)?;
+            match self.reserve(&start, owner.as_ref(), plan_claims(&plan)) {
+                Ok(reservation) => return Some((plan, reservation)),
+                Err(conflict) => debug!("----- Reservation conflict {:?} -----", conflict),
+            }
+        }
+        None
+    }
+}
+
+// Claims held until the plan finishes or is aborted, released when dropped
+#[derive(Debug)]
+pub struct Reservation<'t> {
+    table: &'t ReservationTable,
+    id: u64,
+    claims: Vec<Claim>,
+}
+
+impl<'t> Reservation<'t> {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn claims(&self) -> &[Claim] {
+        &self.claims
+    }
+
+    // Same as dropping the reservation
+    pub fn release(self) {}
+}
+
+impl<'t> Drop for Reservation<'t> {
+    fn drop(&mut self) {
+        self.table.release(self.id);
+    }
+}
+
+// What a plan uses of its start state, see `ReservationTable::plan`
+pub fn plan_claims(plan: &Plan) -> Vec<Claim> {
+    let (nodes, _) = plan;
+    let start = match nodes.first() {
+        Some(node) => node.state(),
+        None => return vec![],
+    };
+    let mut claims = vec![];
+    for (key, value) in start.iter() {
+        let numeric = matches!(value, Field::U64(_) | Field::I64(_));
+        if numeric {
+            let start_value = signed(value);
+            let lowest = nodes
+                .iter()
+                .filter_map(|node| node.state().get(key))
+                .map(|value| signed(&value))
+                .min()
+                .unwrap_or(start_value);
+            if lowest < start_value {
+                claims.push(Claim::quantity(key, (start_value - lowest) as u64));
+            }
+        } else if nodes
+            .iter()
+            .any(|node| node.state().get(key).as_ref() != Some(value))
+        {
+            claims.push(Claim::key(key));
+        }
+    }
+    claims
+}
+
+fn signed(field: &Field) -> i128 {
+    match field {
+        Field::U64(value) => *value as i128,
+        Field::I64(value) => *value as i128,
+        _ => 0,
+    }
+}
+
+fn quantity(state: &State, key: &str) -> u64 {
+    match state.get(key) {
+        Some(Field::U64(value)) => value,
+        Some(Field::I64(value)) => value.max(0) as u64,
+        _ => 0,
+    }
+}
+
+fn reserved_amount(entries: &Entries, key: &str) -> u64 {
+    entries
+        .entries
+        .values()
+        .flat_map(|entry| entry.claims.iter())
+        .map(|claim| match claim {
+            Claim::Quantity { key: other, amount } if other == key => *amount,
+            _ => 0,
+        })
+        .sum()
+}
+
+fn holders(entries: &Entries, key: &str) -> Vec<String> {
+    let mut holders: Vec<String> = vec![];
+    for entry in entries.entries.values() {
+        if entry.claims.iter().any(|claim| claim.key_name() == key)
+            && !holders.contains(&entry.owner)
+        {
+            holders.push(entry.owner.clone());
+        }
+    }
+    holders
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        action::Consequence,
+        test_support::{at_least, consequence},
+        validation::validate_plan,
+    };
+
+    // Takes 3 wood from the stockpile for its owner
+    struct Take(&'static str);
+
+    impl Action for Take {
+        fn key(&self) -> String {
+            self.0.to_owned()
+        }
+
+        fn options(&self, state: &State) -> Vec<(Consequence, u64)> {
+            let wood = quantity(state, "wood");
+            if wood < 3 {
+                return vec![];
+            }
+            let carried = quantity(state, self.0);
+            let result = state
+                .with_field("wood", Field::from(wood - 3))
+                .with_field(self.0, Field::from(carried + 3));
+            vec![(consequence(self, result), 1)]
+        }
+    }
+
+    #[test]
+    fn owners_share_a_stockpile() {
+        let table = ReservationTable::new();
+        let actions: Vec<Box<dyn Action>> = vec![Box::new(Take("alice")), Box::new(Take("bob"))];
+        let start = State::new()
+            .with_field("wood", Field::from(10u64))
+            .with_field("alice", Field::from(0u64))
+            .with_field("bob", Field::from(0u64));
+        let options = PlanOptions::new();
+
+        let goal = at_least("alice", 6);
+        let (plan, _alice) = table
+            .plan(&start, &actions, &goal, &options, "alice", 1)
+            .unwrap();
+        assert_eq!(plan.0[0].state().get("wood"), Some(Field::from(10u64)));
+        assert!(validate_plan(&start, &actions, &plan, &goal).is_valid());
+        assert_eq!(table.reserved("wood"), 6);
+
+        // 4 wood are left, enough to take 3 once
+        assert!(table
+            .plan(&start, &actions, &at_least("bob", 6), &options, "bob", 1)
+            .is_none());
+        let goal = at_least("bob", 3);
+        let (plan, _bob) = table
+            .plan(&start, &actions, &goal, &options, "bob", 1)
+            .unwrap();
+        assert!(validate_plan(&start, &actions, &plan, &goal).is_valid());
+        assert_eq!(table.reserved("wood"), 9);
+    }
+}
@@ -0,0 +1,315 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    constraint::ConstraintSet,
+    field::{Field, FieldType},
+    goal::Goal,
+    requirement::CompareRequirement,
+    state::State,
+};
+
+#[derive(Clone, Debug)]
+pub struct KeySchema {
+    field_type: FieldType,
+    default: Option<Field>,
+    min: Option<f64>,
+    max: Option<f64>,
+    description: String,
+}
+
+impl KeySchema {
+    pub fn new(field_type: FieldType) -> Self {
+        KeySchema {
+            field_type,
+            default: None,
+            min: None,
+            max: None,
+            description: String::new(),
+        }
+    }
+
+    pub fn field_type(&self) -> FieldType {
+        self.field_type
+    }
+
+    pub fn default_value(&self) -> Option<&Field> {
+        self.default.as_ref()
+    }
+
+    pub fn min(&self) -> Option<f64> {
+        self.min
+    }
+
+    pub fn max(&self) -> Option<f64> {
+        self.max
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    pub fn with_default(mut self, default: Field) -> Self {
+        self.default = Some(default);
+        self
+    }
+
+    // Inclusive bounds for numeric keys
+    pub fn with_bounds(mut self, min: Option<f64>, max: Option<f64>) -> Self {
+        self.min = min;
+        self.max = max;
+        self
+    }
+
+    pub fn with_description<S: AsRef<str>>(mut self, description: S) -> Self {
+        self.description = description.as_ref().to_owned();
+        self
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SchemaError {
+    UnknownKey(String),
+    // Declared without a default and missing from the state
+    MissingKey(String),
+    WrongType {
+        key: String,
+        expected: FieldType,
+        found: FieldType,
+    },
+    OutOfBounds {
+        key: String,
+        value: f64,
+        min: Option<f64>,
+        max: Option<f64>,
+    },
+}
+
+// Declares the keys of a domain once, instead of every action filling in
+// its defaults in `Action::prepare`
+#[derive(Clone, Debug, Default)]
+pub struct Schema {
+    keys: BTreeMap<String, KeySchema>,
+}
+
+impl Schema {
+    pub fn new() -> Self {
+        Schema::default()
+    }
+
+    pub fn insert<S: AsRef<str>>(&mut self, key: S, schema: KeySchema) -> Option<KeySchema> {
+        self.keys.insert(key.as_ref().to_owned(), schema)
+    }
+
+    pub fn with_key<S: AsRef<str>>(mut self, key: S, schema: KeySchema) -> Self {
+        self.insert(key, schema);
+        self
+    }
+
+    // Key of the default's type
+    pub fn with_default<S: AsRef<str>>(self, key: S, default: Field) -> Self {
+        self.with_key(
+            key,
+            KeySchema::new(default.field_type()).with_default(default),
+        )
+    }
+
+    pub fn get<S: AsRef<str>>(&self, key: S) -> Option<&KeySchema> {
+        self.keys.get(key.as_ref())
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = (&String, &KeySchema)> {
+        self.keys.iter()
+    }
+
+    // Adds the default of every declared key the state is missing
+    pub fn fill_defaults(&self, state: &State) -> State {
+        let mut filled = state.clone();
+        for (key, schema) in &self.keys {
+            if let Some(default) = &schema.default {
+                if !filled.contains_key(key) {
+                    filled.insert(key, default.clone());
+                }
+            }
+        }
+        filled
+    }
+
+    pub fn validate_state(&self, state: &State) -> Vec<SchemaError> {
+        let mut errors = vec![];
+        for (key, value) in state.iter() {
+            let schema = match self.keys.get(key) {
+                Some(schema) => schema,
+                None => {
+                    errors.push(SchemaError::UnknownKey(key.clone()));
+                    continue;
+                }
+            };
+            if value.field_type() != schema.field_type {
+                errors.push(SchemaError::WrongType {
+                    key: key.clone(),
+                    expected: schema.field_type,
+                    found: value.field_type(),
+                });
+                continue;
+            }
+            if let Some(number) = numeric(value) {
+                let below = matches!(schema.min, Some(min) if number < min);
+                let above = matches!(schema.max, Some(max) if number > max);
+                if below || above {
+                    errors.push(SchemaError::OutOfBounds {
+                        key: key.clone(),
+                        value: number,
+                        min: schema.min,
+                        max: schema.max,
+                    });
+                }
+            }
+        }
+        for (key, schema) in &self.keys {
+            if schema.default.is_none() && !state.contains_key(key) {
+                errors.push(SchemaError::MissingKey(key.clone()));
+            }
+        }
+        errors
+    }
+
+    pub fn validate_goal(&self, goal: &Goal) -> Vec<SchemaError> {
+        let mut errors = vec![];
+        for (key, requirement) in goal.requirements() {
+            let schema = match self.keys.get(key) {
+                Some(schema) => schema,
+                None => {
+                    errors.push(SchemaError::UnknownKey(key.clone()));
+                    continue;
+                }
+            };
+            if let Some(found) = requirement.field_type() {
+                if found != schema.field_type {
+                    errors.push(SchemaError::WrongType {
+                        key: key.clone(),
+                        expected: schema.field_type,
+                        found,
+                    });
+                }
+            }
+        }
+        errors
+    }
+
+    // Fills the defaults, then validates the result
+    pub fn prepare(&self, state: &State) -> Result<State, Vec<SchemaError>> {
+        let filled = self.fill_defaults(state);
+        let errors = self.validate_state(&filled);
+        if errors.is_empty() {
+            Ok(filled)
+        } else {
+            Err(errors)
+        }
+    }
+
+    // The bounds of numeric keys as constraints on every planned state
+    pub fn constraints(&self) -> ConstraintSet {
+        let mut constraints = ConstraintSet::new();
+        for (key, schema) in &self.keys {
+            if let Some(min) = schema
+                .min
+                .and_then(|min| bound(schema.field_type, min.ceil(), min))
+            {
+                constraints = constraints.with_constraint(
+                    format!("{} minimum", key),
+                    key,
+                    Box::new(CompareRequirement::MoreThanEquals(min)),
+                );
+            }
+            if let Some(max) = schema
+                .max
+                .and_then(|max| bound(schema.field_type, max.floor(), max))
+            {
+                constraints = constraints.with_constraint(
+                    format!("{} maximum", key),
+                    key,
+                    Box::new(CompareRequirement::LessThanEquals(max)),
+                );
+            }
+        }
+        constraints
+    }
+}
+
+fn numeric(field: &Field) -> Option<f64> {
+    match field {
+        Field::U64(value) => Some(*value as f64),
+        Field::I64(value) => Some(*value as f64),
+        Field::F64(value) => Some(*value),
+        _ => None,
+    }
+}
+
+// A bound as a field of the key's type, integer keys get the bound rounded
+// inwards
+fn bound(field_type: FieldType, rounded: f64, value: f64) -> Option<Field> {
+    match field_type {
+        FieldType::U64 => Some(Field::from(rounded.max(0.0) as u64)),
+        FieldType::I64 => Some(Field::from(rounded as i64)),
+        FieldType::F64 => Some(Field::from(value)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        action::Action,
+        explain::diagnose_with,
+        planner::{plan_with, PlanOptions},
+        test_support::Heat,
+    };
+
+    fn heat_goal() -> Goal {
+        Goal::new().with_req(
+            "t",
+            Box::new(CompareRequirement::MoreThanEquals(Field::from(2.0))),
+        )
+    }
+
+    #[test]
+    fn float_bounds_are_kept() {
+        let actions: Vec<Box<dyn Action>> = vec![Box::new(Heat)];
+        let schema = Schema::new().with_key(
+            "t",
+            KeySchema::new(FieldType::F64)
+                .with_default(Field::from(0.0))
+                .with_bounds(None, Some(2.0)),
+        );
+        let options = PlanOptions::new().with_schema(schema);
+        let (nodes, cost) = plan_with(&State::new(), &actions, &heat_goal(), &options).unwrap();
+        assert_eq!(cost, 2);
+        assert_eq!(
+            nodes.last().unwrap().state().get("t"),
+            Some(Field::from(2.0))
+        );
+    }
+
+    #[test]
+    fn starts_that_dont_fit_are_not_planned_from() {
+        let actions: Vec<Box<dyn Action>> = vec![Box::new(Heat)];
+        let options = PlanOptions::new().with_schema(Schema::new().with_key(
+            "t",
+            KeySchema::new(FieldType::F64).with_default(Field::from(0.0)),
+        ));
+        let goal = heat_goal();
+        assert!(plan_with(&State::new(), &actions, &goal, &options).is_some());
+
+        let wrong_type = State::new().with_field("t", Field::String("cold".to_owned()));
+        assert!(plan_with(&wrong_type, &actions, &goal, &options).is_none());
+        let unknown_key = State::new().with_field("heater", Field::from(true));
+        assert!(plan_with(&unknown_key, &actions, &goal, &options).is_none());
+
+        let diagnosis = diagnose_with(&unknown_key, &actions, &goal, &options, 10);
+        assert_eq!(
+            diagnosis.schema_errors,
+            vec![SchemaError::UnknownKey("heater".to_owned())]
+        );
+    }
+}
@@ -1,12 +1,16 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::hash::{Hash, Hasher};
 
+use serde_json::Value;
+
 use crate::field::Field;
 use crate::goal::Goal;
+use crate::key::{FieldValue, Key};
+use crate::requirement::BoxedRequirement;
 
 #[derive(Clone)]
 pub struct State {
-    fields: HashMap<String, Field>,
+    fields: BTreeMap<String, Field>,
 }
 
 impl PartialOrd for State {
@@ -34,7 +38,7 @@ impl Hash for State {
 impl State {
     pub fn new() -> Self {
         State {
-            fields: HashMap::new(),
+            fields: BTreeMap::new(),
         }
     }
 
@@ -42,6 +46,10 @@ impl State {
         self.fields.insert(key.as_ref().to_owned(), value)
     }
 
+    pub fn remove<S: AsRef<str>>(&mut self, key: S) -> Option<Field> {
+        self.fields.remove(key.as_ref())
+    }
+
     pub fn get<S: AsRef<str>>(&self, key: S) -> Option<Field> {
         self.fields.get(key.as_ref()).map(|value| value.clone())
     }
@@ -62,10 +70,27 @@ impl State {
         self.get(key).map(|f| f.as_f64()).flatten()
     }
 
+    // `None` when the key is missing or holds another type
+    pub fn value<T: FieldValue>(&self, key: Key<T>) -> Option<T> {
+        self.fields.get(key.name()).and_then(T::from_field)
+    }
+
+    pub fn set<T: FieldValue>(&mut self, key: Key<T>, value: T) -> Option<Field> {
+        self.insert(key.name(), value.into_field())
+    }
+
+    pub fn with_value<T: FieldValue>(&self, key: Key<T>, value: T) -> Self {
+        self.with_field(key.name(), value.into_field())
+    }
+
     pub fn contains_key<S: AsRef<str>>(&self, key: S) -> bool {
         self.fields.contains_key(key.as_ref())
     }
 
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Field)> {
+        self.fields.iter()
+    }
+
     pub fn distance_to(&self, other: &State) -> u64 {
         debug!("----- Looking for distance -----");
         debug!("From: {:?}", self);
@@ -93,12 +118,7 @@ impl State {
 
         let mut distance: u64 = 0;
         for (key, value) in goal.requirements() {
-            let self_value = self.get(key);
-            if let Some(self_value) = self_value {
-                distance += value.distance_from(&self_value);
-            } else {
-                distance += 1;
-            }
+            distance += self.distance_to_requirement(key, value);
         }
 
         debug!("= {}", distance);
@@ -106,6 +126,26 @@ impl State {
         distance
     }
 
+    pub fn to_value(&self) -> Value {
+        Value::Object(
+            self.fields
+                .iter()
+                .map(|(key, value)| (key.clone(), value.to_value()))
+                .collect(),
+        )
+    }
+
+    pub fn distance_to_requirement<S: AsRef<str>>(
+        &self,
+        key: S,
+        requirement: &BoxedRequirement,
+    ) -> u64 {
+        match self.fields.get(key.as_ref()) {
+            Some(value) => requirement.distance_from(value),
+            None => 1,
+        }
+    }
+
     pub fn with_field<S: AsRef<str>>(&self, key: S, value: Field) -> Self {
         let mut clone = self.clone();
         clone.insert(key, value);
@@ -118,3 +158,20 @@ impl std::fmt::Debug for State {
         self.fields.fmt(f)
     }
 }
+
+// Exact fields of a state, to find duplicates with. `State`'s own equality
+// goes through the distance between the states, which can't tell close
+// floats apart and doesn't support JSON values
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub(crate) struct StateKey(Vec<(String, Field)>);
+
+impl StateKey {
+    pub(crate) fn new(state: &State) -> Self {
+        StateKey(
+            state
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+        )
+    }
+}
@@ -0,0 +1,38 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+#[derive(Clone, Debug, Default)]
+pub struct SearchStats {
+    pub nodes_expanded: u64,
+    pub nodes_generated: u64,
+    pub duplicate_hits: u64,
+    pub max_open_set: usize,
+    pub heuristic_evaluations: u64,
+    pub prepare_time: Duration,
+    pub options_time: Duration,
+    pub heuristic_time: Duration,
+    // Consequences generated by each action, keyed by `Action::key`
+    pub action_expansions: BTreeMap<String, u64>,
+    // Consequences pruned by each constraint, keyed by `Constraint::name`
+    pub constraint_violations: BTreeMap<String, u64>,
+    // Consequences pruned because they could no longer meet a deadline
+    pub deadline_pruned: u64,
+}
+
+impl SearchStats {
+    pub fn new() -> Self {
+        SearchStats::default()
+    }
+
+    pub fn branching_factor(&self) -> f64 {
+        if self.nodes_expanded == 0 {
+            0.0
+        } else {
+            self.nodes_generated as f64 / self.nodes_expanded as f64
+        }
+    }
+
+    pub fn pruned(&self) -> u64 {
+        self.constraint_violations.values().sum()
+    }
+}
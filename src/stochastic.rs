@@ -0,0 +1,295 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use serde_json::Value;
+
+use crate::{
+    action::{Action, Outcome},
+    goal::Goal,
+    planner::{prepare_checked, Node, Plan, PlanOptions},
+    state::{State, StateKey},
+};
+
+#[derive(Clone, Debug)]
+pub struct PolicyRule {
+    pub action: String,
+    pub argument: Option<Value>,
+    pub cost: u64,
+    pub outcomes: Vec<Outcome>,
+    // Expected cost of reaching the goal when following the policy from the
+    // state of the rule
+    pub expected_cost: f64,
+}
+
+// Maps every state the policy can end up in to the choice to make there
+#[derive(Clone, Debug)]
+pub struct Policy {
+    start: State,
+    rules: HashMap<StateKey, PolicyRule>,
+    expected_cost: f64,
+}
+
+impl Policy {
+    pub fn start(&self) -> &State {
+        &self.start
+    }
+
+    pub fn expected_cost(&self) -> f64 {
+        self.expected_cost
+    }
+
+    // `None` once the goal is reached or the horizon ran out
+    pub fn decide(&self, state: &State) -> Option<&PolicyRule> {
+        self.rules.get(&StateKey::new(state))
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    // Follows the policy assuming the most probable outcome of every choice
+    pub fn most_likely_plan(&self) -> Plan {
+        let mut nodes = vec![Node::State(self.start.clone())];
+        let mut cost = 0;
+        let mut state = self.start.clone();
+        let mut visited = HashSet::new();
+        while let Some(rule) = self.decide(&state) {
+            if !visited.insert(StateKey::new(&state)) {
+                break;
+            }
+            let outcome = match rule.outcomes.iter().max_by(|a, b| {
+                a.probability
+                    .partial_cmp(&b.probability)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            }) {
+                Some(outcome) => outcome,
+                None => break,
+            };
+            cost += rule.cost;
+            state = outcome.consequence.result.clone();
+            nodes.push(Node::Consequence(outcome.consequence.clone()));
+        }
+        (nodes, cost)
+    }
+}
+
+// Expectimax over `Action::outcomes` up to `horizon` choices deep. Choices
+// minimize the cost plus the expected cost of their outcomes, states at the
+// horizon are valued by their distance to the goal and dead ends can't reach
+// it at all. The policy may run out of horizon before reaching the goal, it
+// is only `None` when every choice at the start risks a dead end
+pub fn plan_expectimax<'a>(
+    start: &State,
+    actions: &[Box<dyn Action + 'a>],
+    goal: &Goal,
+    horizon: usize,
+) -> Option<Policy> {
+    plan_expectimax_with(start, actions, goal, &PlanOptions::new(), horizon)
+}
+
+// Uses the schema of the options like `plan_with`, a choice is left out
+// when one of its outcomes violates the constraints or schema bounds
+pub fn plan_expectimax_with<'a>(
+    start: &State,
+    actions: &[Box<dyn Action + 'a>],
+    goal: &Goal,
+    options: &PlanOptions,
+    horizon: usize,
+) -> Option<Policy> {
+    if !options.fits_schema(goal) {
+        return None;
+    }
+    let start = prepare_checked(start, actions, options)?;
+    let mut search = Expectimax {
+        actions,
+        goal,
+        options,
+        values: HashMap::new(),
+    };
+    let expected_cost = search.value(&start, horizon);
+    if !expected_cost.is_finite() {
+        return None;
+    }
+
+    // A state reachable at several depths keeps the choice made with the
+    // most horizon left
+    let mut rules = HashMap::new();
+    let mut queue = VecDeque::new();
+    queue.push_back((start.clone(), horizon));
+    while let Some((state, depth)) = queue.pop_front() {
+        let key = StateKey::new(&state);
+        if rules.contains_key(&key) {
+            continue;
+        }
+        let (value, choice) = match search.values.get(&(key.clone(), depth)) {
+            Some((value, Some(choice))) => (*value, choice.clone()),
+            _ => continue,
+        };
+        let (action, argument) = match choice.0.first() {
+            Some(outcome) => (
+                outcome.consequence.action.clone(),
+                outcome.consequence.argument.clone(),
+            ),
+            None => continue,
+        };
+        for outcome in &choice.0 {
+            queue.push_back((outcome.consequence.result.clone(), depth - 1));
+        }
+        rules.insert(
+            key,
+            PolicyRule {
+                action,
+                argument,
+                cost: choice.1,
+                outcomes: choice.0,
+                expected_cost: value,
+            },
+        );
+    }
+
+    Some(Policy {
+        start,
+        rules,
+        expected_cost,
+    })
+}
+
+type Choice = (Vec<Outcome>, u64);
+
+struct Expectimax<'s, 'a> {
+    actions: &'s [Box<dyn Action + 'a>],
+    goal: &'s Goal,
+    options: &'s PlanOptions,
+    values: HashMap<(StateKey, usize), (f64, Option<Choice>)>,
+}
+
+impl<'s, 'a> Expectimax<'s, 'a> {
+    fn value(&mut self, state: &State, depth: usize) -> f64 {
+        let distance = state.distance_to_goal(self.goal);
+        if distance == 0 {
+            return 0.0;
+        }
+        if depth == 0 {
+            return distance as f64;
+        }
+        let key = (StateKey::new(state), depth);
+        if let Some((value, _)) = self.values.get(&key) {
+            return *value;
+        }
+
+        let mut best: (f64, Option<Choice>) = (f64::INFINITY, None);
+        for action in self.actions {
+            for (outcomes, cost) in action.outcomes(state) {
+                if outcomes
+                    .iter()
+                    .any(|outcome| self.options.check(&outcome.consequence.result).is_some())
+                {
+                    continue;
+                }
+                let mut expected = cost as f64;
+                for outcome in &outcomes {
+                    // Zero-probability dead ends would turn the sum into NaN
+                    if outcome.probability > 0.0 {
+                        expected += outcome.probability
+                            * self.value(&outcome.consequence.result, depth - 1);
+                    }
+                }
+                if expected < best.0 {
+                    best = (expected, Some((outcomes, cost)));
+                }
+            }
+        }
+        let value = best.0;
+        self.values.insert(key, best);
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        action::Consequence,
+        field::Field,
+        test_support::{consequence, equals},
+    };
+    use serde_json::json;
+
+    // Adds one to "x" and lands on either side, the side is a JSON value
+    struct Toss;
+
+    impl Action for Toss {
+        fn key(&self) -> String {
+            "toss".to_owned()
+        }
+
+        fn options(&self, _state: &State) -> Vec<(Consequence, u64)> {
+            vec![]
+        }
+
+        fn outcomes(&self, state: &State) -> Vec<(Vec<Outcome>, u64)> {
+            let x = state.get_as_u64("x").unwrap_or(0);
+            let outcome = |side: &str| Outcome {
+                consequence: consequence(
+                    self,
+                    state
+                        .with_field("x", Field::from(x + 1))
+                        .with_field("side", Field::Value(json!({ "side": side }))),
+                ),
+                probability: 0.5,
+            };
+            vec![(vec![outcome("heads"), outcome("tails")], 1)]
+        }
+    }
+
+    // Finds the thing half of the time and otherwise stays where it is
+    struct Search;
+
+    impl Action for Search {
+        fn key(&self) -> String {
+            "search".to_owned()
+        }
+
+        fn options(&self, _state: &State) -> Vec<(Consequence, u64)> {
+            vec![]
+        }
+
+        fn outcomes(&self, state: &State) -> Vec<(Vec<Outcome>, u64)> {
+            let outcome = |result: State| Outcome {
+                consequence: consequence(self, result),
+                probability: 0.5,
+            };
+            vec![(
+                vec![
+                    outcome(state.with_field("found", Field::from(1u64))),
+                    outcome(state.clone()),
+                ],
+                1,
+            )]
+        }
+    }
+
+    #[test]
+    fn states_holding_json_values_get_their_own_rules() {
+        let actions: Vec<Box<dyn Action>> = vec![Box::new(Toss)];
+        let start = State::new().with_field("x", Field::from(0u64));
+        let policy = plan_expectimax(&start, &actions, &equals("x", 3), 3).unwrap();
+        // Once for the start and for each side at one and at two tosses
+        assert_eq!(policy.len(), 5);
+        assert_eq!(policy.expected_cost(), 3.0);
+        assert_eq!(policy.most_likely_plan().1, 3);
+    }
+
+    #[test]
+    fn policies_may_run_out_of_horizon() {
+        let actions: Vec<Box<dyn Action>> = vec![Box::new(Search)];
+        let policy = plan_expectimax(&State::new(), &actions, &equals("found", 1), 2).unwrap();
+        // Half of the time nothing is found within the horizon, the distance
+        // left is added for it
+        assert_eq!(policy.expected_cost(), 1.75);
+        assert!(policy.decide(policy.start()).is_some());
+    }
+}
@@ -0,0 +1,413 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap};
+use std::time::Instant;
+
+use serde_json::Value;
+
+use crate::{
+    action::{step_label, Action},
+    goal::Goal,
+    partial_order::{deorder_with, step_accesses},
+    planner::{prepare_checked, Node, Plan, PlanOptions, TieBreaker},
+    state::{State, StateKey},
+};
+
+#[derive(Clone, Debug)]
+pub struct ScheduledStep {
+    // Index of the step's node in the plan
+    pub step: usize,
+    pub action: String,
+    pub argument: Option<Value>,
+    pub start: u64,
+    pub end: u64,
+    pub resources: Vec<String>,
+    // Steps that have to finish before this one starts
+    pub depends_on: Vec<usize>,
+}
+
+impl ScheduledStep {
+    pub fn label(&self) -> String {
+        step_label(&self.action, self.argument.as_ref())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Schedule {
+    pub steps: Vec<ScheduledStep>,
+    pub makespan: u64,
+}
+
+impl Schedule {
+    // One row per step, `width` characters for the whole makespan
+    pub fn to_gantt(&self, width: usize) -> String {
+        let width = width.max(1);
+        let label_width = self
+            .steps
+            .iter()
+            .map(|step| step.label().len())
+            .max()
+            .unwrap_or(0);
+        let scale = |time: u64| -> usize {
+            if self.makespan == 0 {
+                0
+            } else {
+                (time as usize * width + self.makespan as usize / 2) / self.makespan as usize
+            }
+        };
+
+        let mut gantt = String::new();
+        for step in &self.steps {
+            let from = scale(step.start).min(width - 1);
+            let to = scale(step.end).max(from + 1).min(width);
+            gantt.push_str(&format!(
+                "{:<label_width$} |{}{}{}| {}-{}\n",
+                step.label(),
+                " ".repeat(from),
+                "#".repeat(to - from),
+                " ".repeat(width - to),
+                step.start,
+                step.end,
+                label_width = label_width
+            ));
+        }
+        gantt
+    }
+}
+
+// Searches for the plan with the shortest makespan when scheduled with
+// `schedule`, ties go to the plan closer to the goal and then to the
+// cheaper one. The cost of the returned plan is the sum of its step costs,
+// deadlines and constraints of the options apply as in `plan_with`
+pub fn plan_temporal<'a>(
+    start: &State,
+    actions: &[Box<dyn Action + 'a>],
+    goal: &Goal,
+    options: &PlanOptions,
+) -> Option<(Plan, Schedule)> {
+    let plan = search(start, actions, goal, options)?;
+    let schedule = schedule_with(start, actions, &plan, options)?;
+    Some((plan, schedule))
+}
+
+// Starts every step as soon as the steps it depends on in the deordered
+// plan and the earlier steps sharing one of its resources have finished,
+// which gives the shortest makespan for the order of the plan. Returns
+// `None` if a step can no longer be replayed with the given actions
+pub fn schedule<'a>(
+    start: &State,
+    actions: &[Box<dyn Action + 'a>],
+    plan: &Plan,
+) -> Option<Schedule> {
+    schedule_with(start, actions, plan, &PlanOptions::new())
+}
+
+// Deorders the plan with `deorder_with` and the options
+pub fn schedule_with<'a>(
+    start: &State,
+    actions: &[Box<dyn Action + 'a>],
+    plan: &Plan,
+    options: &PlanOptions,
+) -> Option<Schedule> {
+    let partial = deorder_with(start, actions, plan, options)?;
+    let mut steps: Vec<ScheduledStep> = vec![];
+    for (index, step) in partial.steps().iter().enumerate() {
+        let consequence = &step.consequence;
+        let action = actions
+            .iter()
+            .find(|action| action.key() == consequence.action)?;
+        let resources = action.resources(consequence);
+        let predecessors = partial.predecessors(index);
+
+        let mut start_time = 0;
+        for (previous_index, previous) in steps.iter().enumerate() {
+            let shares_resource = previous
+                .resources
+                .iter()
+                .any(|resource| resources.contains(resource));
+            if shares_resource || predecessors.contains(&previous_index) {
+                start_time = start_time.max(previous.end);
+            }
+        }
+
+        steps.push(ScheduledStep {
+            step: step.step,
+            action: consequence.action.clone(),
+            argument: consequence.argument.clone(),
+            start: start_time,
+            end: start_time + action.duration(consequence, step.cost),
+            resources,
+            depends_on: predecessors
+                .iter()
+                .map(|&previous| partial.steps()[previous].step)
+                .collect(),
+        });
+    }
+
+    let makespan = steps.iter().map(|step| step.end).max().unwrap_or(0);
+    Some(Schedule { steps, makespan })
+}
+
+// Each step starts as early as `schedule` would start it, so the makespan
+// of a path only grows and the first goal taken from the open set has the
+// shortest one
+fn search<'a>(
+    start: &State,
+    actions: &[Box<dyn Action + 'a>],
+    goal: &Goal,
+    options: &PlanOptions,
+) -> Option<Plan> {
+    if !options.fits_schema(goal) {
+        return None;
+    }
+    let start = prepare_checked(start, actions, options)?;
+    if !goal.can_meet_deadlines(&start, 0) {
+        debug!("----- Deadline can't be met -----");
+        return None;
+    }
+    let started = Instant::now();
+    let mut expanded = 0;
+    let mut tie_breaker = TieBreaker::new(options.seed());
+    let mut nodes = vec![TimedNode {
+        node: Node::State(start),
+        parent: None,
+        frontier: Frontier::default(),
+    }];
+    let mut open = BinaryHeap::new();
+    open.push(Reverse((
+        0,
+        nodes[0].node.state().distance_to_goal(goal),
+        0,
+        tie_breaker.next(),
+        0,
+    )));
+    // Frontiers and costs each state was expanded with
+    let mut closed: HashMap<StateKey, Vec<(Frontier, u64)>> = HashMap::new();
+
+    while let Some(Reverse((_, distance, cost, _, index))) = open.pop() {
+        if distance == 0 {
+            let mut path = vec![nodes[index].node.clone()];
+            let mut current = index;
+            while let Some(parent) = nodes[current].parent {
+                path.push(nodes[parent].node.clone());
+                current = parent;
+            }
+            path.reverse();
+            return Some((path, cost));
+        }
+        // A later start for every key and resource can't finish sooner
+        let expansions = closed
+            .entry(StateKey::new(nodes[index].node.state()))
+            .or_default();
+        if expansions.iter().any(|(frontier, expanded_cost)| {
+            *expanded_cost <= cost && frontier.is_before(&nodes[index].frontier)
+        }) {
+            continue;
+        }
+        expansions.push((nodes[index].frontier.clone(), cost));
+
+        if options.budget_exhausted(expanded, started) {
+            debug!("----- Search budget exhausted -----");
+            break;
+        }
+        expanded += 1;
+        let state = nodes[index].node.state().clone();
+        for action in actions {
+            for (consequence, step_cost) in action.options(&state) {
+                let cost = cost + step_cost;
+                if options.check(&consequence.result).is_some()
+                    || !goal.can_meet_deadlines(&consequence.result, cost)
+                {
+                    continue;
+                }
+                let (reads, writes) = step_accesses(&**action, &state, &consequence.result);
+                let frontier = nodes[index].frontier.with_step(
+                    reads.as_ref(),
+                    &writes,
+                    &action.resources(&consequence),
+                    action.duration(&consequence, step_cost),
+                );
+                open.push(Reverse((
+                    frontier.makespan,
+                    consequence.result.distance_to_goal(goal),
+                    cost,
+                    tie_breaker.next(),
+                    nodes.len(),
+                )));
+                nodes.push(TimedNode {
+                    node: Node::Consequence(consequence),
+                    parent: Some(index),
+                    frontier,
+                });
+            }
+        }
+    }
+
+    None
+}
+
+struct TimedNode {
+    node: Node,
+    parent: Option<usize>,
+    frontier: Frontier,
+}
+
+// When the keys and resources of a partially scheduled plan are free again
+#[derive(Clone, Default)]
+struct Frontier {
+    // Ends of the last step writing each key
+    written: BTreeMap<String, u64>,
+    // Ends of the steps reading each key
+    read: BTreeMap<String, u64>,
+    // Ends of the last step holding each resource
+    held: BTreeMap<String, u64>,
+    // End of the last step without declared reads, which every later step
+    // waits for
+    barrier: u64,
+    makespan: u64,
+}
+
+impl Frontier {
+    fn with_step(
+        &self,
+        reads: Option<&BTreeSet<String>>,
+        writes: &BTreeSet<String>,
+        resources: &[String],
+        duration: u64,
+    ) -> Frontier {
+        let time = |times: &BTreeMap<String, u64>, key: &String| *times.get(key).unwrap_or(&0);
+        let start = match reads {
+            // Like `deorder`, such a step waits for every earlier one
+            None => self.makespan,
+            Some(reads) => reads
+                .iter()
+                .map(|key| time(&self.written, key))
+                .chain(
+                    writes
+                        .iter()
+                        .map(|key| time(&self.written, key).max(time(&self.read, key))),
+                )
+                .chain(resources.iter().map(|resource| time(&self.held, resource)))
+                .fold(self.barrier, u64::max),
+        };
+        let end = start + duration;
+
+        let mut frontier = self.clone();
+        match reads {
+            None => frontier.barrier = end,
+            Some(reads) => {
+                for key in reads {
+                    let read = frontier.read.entry(key.clone()).or_default();
+                    *read = (*read).max(end);
+                }
+            }
+        }
+        for key in writes {
+            frontier.written.insert(key.clone(), end);
+        }
+        for resource in resources {
+            frontier.held.insert(resource.clone(), end);
+        }
+        frontier.makespan = self.makespan.max(end);
+        frontier
+    }
+
+    // Whether nothing is free later here than in `other`
+    fn is_before(&self, other: &Frontier) -> bool {
+        let times_before = |times: &BTreeMap<String, u64>, others: &BTreeMap<String, u64>| {
+            times
+                .iter()
+                .all(|(key, time)| time <= others.get(key).unwrap_or(&0))
+        };
+        self.barrier <= other.barrier
+            && self.makespan <= other.makespan
+            && times_before(&self.written, &other.written)
+            && times_before(&self.read, &other.read)
+            && times_before(&self.held, &other.held)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        action::Consequence, field::Field, planner::plan_with, requirement::CompareRequirement,
+    };
+
+    // Sets `sets` once every key of `needs` is set
+    struct Task {
+        name: &'static str,
+        needs: Vec<&'static str>,
+        sets: &'static str,
+        cost: u64,
+        duration: u64,
+    }
+
+    impl Action for Task {
+        fn key(&self) -> String {
+            self.name.to_owned()
+        }
+
+        fn options(&self, state: &State) -> Vec<(Consequence, u64)> {
+            let is_set = |key: &str| state.get(key) == Some(Field::from(true));
+            if is_set(self.sets) || !self.needs.iter().all(|key| is_set(key)) {
+                return vec![];
+            }
+            vec![(
+                Consequence {
+                    action: self.key(),
+                    argument: None,
+                    result: state.with_field(self.sets, Field::from(true)),
+                },
+                self.cost,
+            )]
+        }
+
+        fn reads(&self) -> Vec<String> {
+            let mut reads: Vec<String> = self.needs.iter().map(|key| key.to_string()).collect();
+            reads.push(self.sets.to_owned());
+            reads
+        }
+
+        fn duration(&self, _consequence: &Consequence, _cost: u64) -> u64 {
+            self.duration
+        }
+    }
+
+    #[test]
+    fn finds_the_shortest_makespan_over_the_cheapest_plan() {
+        let task = |name, needs, sets, cost, duration| -> Box<dyn Action> {
+            Box::new(Task {
+                name,
+                needs,
+                sets,
+                cost,
+                duration,
+            })
+        };
+        let actions = vec![
+            task("walk", vec![], "at_market", 1, 3),
+            task("smelt", vec![], "ingot", 3, 5),
+            task("buy", vec!["at_market"], "ingot", 1, 4),
+        ];
+        let goal = Goal::new()
+            .with_req(
+                "ingot",
+                Box::new(CompareRequirement::Equals(Field::from(true))),
+            )
+            .with_req(
+                "at_market",
+                Box::new(CompareRequirement::Equals(Field::from(true))),
+            );
+        let start = State::new();
+        let options = PlanOptions::new();
+
+        let cheapest = plan_with(&start, &actions, &goal, &options).unwrap();
+        assert_eq!(cheapest.1, 2);
+        assert_eq!(schedule(&start, &actions, &cheapest).unwrap().makespan, 7);
+
+        let (plan, schedule) = plan_temporal(&start, &actions, &goal, &options).unwrap();
+        assert_eq!(plan.1, 4);
+        assert_eq!(schedule.makespan, 5);
+        assert!(schedule.steps.iter().all(|step| step.start == 0));
+    }
+}
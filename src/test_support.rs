@@ -0,0 +1,128 @@
+// Actions and goals shared by the unit tests
+use serde_json::json;
+
+use crate::{
+    action::{Action, Consequence},
+    field::Field,
+    goal::Goal,
+    requirement::CompareRequirement,
+    state::State,
+};
+
+// Adds one to "x" at its cost once "x" is in the state, advancing "clock"
+// by one as well when the state has one
+pub struct Add(pub u64);
+
+impl Action for Add {
+    fn key(&self) -> String {
+        "add".to_owned()
+    }
+
+    fn options(&self, state: &State) -> Vec<(Consequence, u64)> {
+        let x = match state.get_as_u64("x") {
+            Some(x) => x,
+            None => return vec![],
+        };
+        let mut result = state.with_field("x", Field::from(x + 1));
+        if let Some(clock) = state.get_as_u64("clock") {
+            result = result.with_field("clock", Field::from(clock + 1));
+        }
+        vec![(consequence(self, result), self.0)]
+    }
+}
+
+// Chops 2 wood for 1 or 8 wood for 3, both without an argument
+pub struct Chop;
+
+impl Action for Chop {
+    fn key(&self) -> String {
+        "chop".to_owned()
+    }
+
+    fn options(&self, state: &State) -> Vec<(Consequence, u64)> {
+        let wood = state.get_as_u64("wood").unwrap_or(0);
+        vec![
+            (
+                consequence(self, state.with_field("wood", Field::from(wood + 2))),
+                1,
+            ),
+            (
+                consequence(self, state.with_field("wood", Field::from(wood + 8))),
+                3,
+            ),
+        ]
+    }
+}
+
+// Walks right or up a `size` by `size` grid, the position is a JSON value
+pub struct Walk(pub u64);
+
+impl Action for Walk {
+    fn key(&self) -> String {
+        "walk".to_owned()
+    }
+
+    fn options(&self, state: &State) -> Vec<(Consequence, u64)> {
+        let position = state
+            .get("position")
+            .and_then(|field| field.as_value())
+            .unwrap_or_default();
+        let x = position["x"].as_u64().unwrap_or(0);
+        let y = position["y"].as_u64().unwrap_or(0);
+        [(x + 1, y), (x, y + 1)]
+            .iter()
+            .filter(|(x, y)| *x < self.0 && *y < self.0)
+            .map(|(x, y)| {
+                let position = Field::Value(json!({ "x": x, "y": y }));
+                (consequence(self, state.with_field("position", position)), 1)
+            })
+            .collect()
+    }
+}
+
+// Heats "t" by 1.0 or by 5.0, both at a cost of 1
+pub struct Heat;
+
+impl Action for Heat {
+    fn key(&self) -> String {
+        "heat".to_owned()
+    }
+
+    fn options(&self, state: &State) -> Vec<(Consequence, u64)> {
+        let t = match state.get("t") {
+            Some(Field::F64(t)) => t,
+            _ => 0.0,
+        };
+        [1.0, 5.0]
+            .iter()
+            .map(|step| {
+                (
+                    consequence(self, state.with_field("t", Field::from(t + step))),
+                    1,
+                )
+            })
+            .collect()
+    }
+}
+
+pub fn consequence(action: &dyn Action, result: State) -> Consequence {
+    Consequence {
+        action: action.key(),
+        argument: None,
+        result,
+    }
+}
+
+pub fn equals(key: &str, value: u64) -> Goal {
+    Goal::new().with_req(
+        key,
+        Box::new(CompareRequirement::Equals(Field::from(value))),
+    )
+}
+
+pub fn at_least(key: &str, value: u64) -> Goal {
+    Goal::new().with_req(
+        key,
+        Box::new(CompareRequirement::MoreThanEquals(Field::from(value))),
+    )
+}
@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+
+use serde_json::{json, Value};
+
+use crate::{action::step_label, constraint::Violation, planner::Node, state::State};
+
+#[derive(Clone, Debug)]
+pub struct TraceNode {
+    pub id: usize,
+    pub parent: Option<usize>,
+    pub action: Option<String>,
+    pub argument: Option<Value>,
+    pub cost: u64,
+    pub estimate: u64,
+    pub state: State,
+    pub goal: bool,
+}
+
+impl TraceNode {
+    pub fn label(&self) -> String {
+        node_label(self.action.as_deref(), self.argument.as_ref())
+    }
+}
+
+// A consequence that was never added to the search because its result
+// violates a constraint
+#[derive(Clone, Debug)]
+pub struct PrunedNode {
+    pub parent: usize,
+    pub action: Option<String>,
+    pub argument: Option<Value>,
+    pub state: State,
+    pub violation: Violation,
+}
+
+impl PrunedNode {
+    pub fn label(&self) -> String {
+        node_label(self.action.as_deref(), self.argument.as_ref())
+    }
+}
+
+fn node_label(action: Option<&str>, argument: Option<&Value>) -> String {
+    match action {
+        Some(action) => step_label(action, argument),
+        None => "start".to_owned(),
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SearchTrace {
+    nodes: Vec<TraceNode>,
+    // Position of every id in `nodes`
+    positions: HashMap<usize, usize>,
+    plan: Vec<usize>,
+    pruned: Vec<PrunedNode>,
+}
+
+impl SearchTrace {
+    pub fn new() -> Self {
+        SearchTrace::default()
+    }
+
+    // Nodes in the order they were first expanded. A node expanded again
+    // after a cheaper path to it was found keeps its place, with the parent
+    // and cost of the cheaper path
+    pub fn nodes(&self) -> &[TraceNode] {
+        &self.nodes
+    }
+
+    // Ids of the nodes on the returned plan, empty if planning failed
+    pub fn plan(&self) -> &[usize] {
+        &self.plan
+    }
+
+    pub fn pruned(&self) -> &[PrunedNode] {
+        &self.pruned
+    }
+
+    pub fn get(&self, id: usize) -> Option<&TraceNode> {
+        self.positions
+            .get(&id)
+            .map(|&position| &self.nodes[position])
+    }
+
+    pub(crate) fn record(
+        &mut self,
+        id: usize,
+        parent: Option<usize>,
+        node: &Node,
+        cost: u64,
+        estimate: u64,
+        goal: bool,
+    ) {
+        let (action, argument) = match node {
+            Node::Consequence(consequence) => (
+                Some(consequence.action.clone()),
+                consequence.argument.clone(),
+            ),
+            Node::State(_) => (None, None),
+        };
+        let node = TraceNode {
+            id,
+            parent,
+            action,
+            argument,
+            cost,
+            estimate,
+            state: node.state().clone(),
+            goal,
+        };
+        match self.positions.get(&id) {
+            Some(&position) => self.nodes[position] = node,
+            None => {
+                self.positions.insert(id, self.nodes.len());
+                self.nodes.push(node);
+            }
+        }
+    }
+
+    pub(crate) fn record_pruned(&mut self, parent: usize, node: &Node, violation: Violation) {
+        let (action, argument) = match node {
+            Node::Consequence(consequence) => (
+                Some(consequence.action.clone()),
+                consequence.argument.clone(),
+            ),
+            Node::State(_) => (None, None),
+        };
+        self.pruned.push(PrunedNode {
+            parent,
+            action,
+            argument,
+            state: node.state().clone(),
+            violation,
+        });
+    }
+
+    pub(crate) fn set_plan(&mut self, plan: Vec<usize>) {
+        self.plan = plan;
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph search {\n    node [shape=box];\n");
+        for (order, node) in self.nodes.iter().enumerate() {
+            let style = if node.goal {
+                ", style=filled, fillcolor=palegreen"
+            } else if self.plan.contains(&node.id) {
+                ", style=filled, fillcolor=lightblue"
+            } else {
+                ""
+            };
+            dot.push_str(&format!(
+                "    n{} [label=\"#{} {}\\ng={} h={} f={}\"{}];\n",
+                node.id,
+                order,
+                escape(&node.label()),
+                node.cost,
+                node.estimate,
+                node.cost + node.estimate,
+                style
+            ));
+        }
+        for node in &self.nodes {
+            if let Some(parent) = node.parent {
+                let style = if self.plan.contains(&node.id) && self.plan.contains(&parent) {
+                    " [penwidth=2]"
+                } else {
+                    ""
+                };
+                dot.push_str(&format!("    n{} -> n{}{};\n", parent, node.id, style));
+            }
+        }
+        for (index, pruned) in self.pruned.iter().enumerate() {
+            dot.push_str(&format!(
+                "    p{} [label=\"{}\\n{}\", color=red, fontcolor=red];\n",
+                index,
+                escape(&pruned.label()),
+                escape(&pruned.violation.description())
+            ));
+            dot.push_str(&format!(
+                "    n{} -> p{} [style=dashed, color=red];\n",
+                pruned.parent, index
+            ));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    pub fn to_json(&self) -> Value {
+        let nodes: Vec<Value> = self
+            .nodes
+            .iter()
+            .map(|node| {
+                json!({
+                    "id": node.id,
+                    "parent": node.parent,
+                    "action": node.action,
+                    "argument": node.argument,
+                    "g": node.cost,
+                    "h": node.estimate,
+                    "state": node.state.to_value(),
+                    "goal": node.goal,
+                })
+            })
+            .collect();
+        let pruned: Vec<Value> = self
+            .pruned
+            .iter()
+            .map(|pruned| {
+                json!({
+                    "parent": pruned.parent,
+                    "action": pruned.action,
+                    "argument": pruned.argument,
+                    "state": pruned.state.to_value(),
+                    "constraint": pruned.violation.constraint,
+                    "reason": pruned.violation.description(),
+                })
+            })
+            .collect();
+        json!({
+            "nodes": nodes,
+            "plan": self.plan,
+            "pruned": pruned,
+        })
+    }
+}
+
+pub(crate) fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
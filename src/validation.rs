@@ -0,0 +1,247 @@
+use crate::{
+    action::{Action, Consequence},
+    goal::Goal,
+    planner::{prepare_with, Node, Plan, PlanOptions},
+    state::State,
+};
+
+#[derive(Clone, Debug)]
+pub enum InvalidReason {
+    // No action with the step's key is in the action set
+    UnknownAction(String),
+    // The action no longer offers the recorded consequence
+    NotOffered,
+    // Steps after the start must be consequences
+    NotAConsequence,
+    // The consequence is still offered, at another cost than recorded
+    CostChanged { recorded: u64, replayed: u64 },
+}
+
+#[derive(Clone, Debug)]
+pub struct InvalidStep {
+    pub step: usize,
+    pub reason: InvalidReason,
+}
+
+#[derive(Clone, Debug)]
+pub struct PlanValidation {
+    pub invalid_step: Option<InvalidStep>,
+    pub recorded_cost: u64,
+    // Cost of the replayed steps, up to the first invalid one
+    pub replayed_cost: u64,
+    // Cost of every replayed step, in plan order
+    pub step_costs: Vec<u64>,
+    // State after the last step that could be replayed
+    pub final_state: State,
+    pub goal_satisfied: bool,
+}
+
+impl PlanValidation {
+    pub fn is_valid(&self) -> bool {
+        self.invalid_step.is_none()
+            && self.replayed_cost == self.recorded_cost
+            && self.goal_satisfied
+    }
+}
+
+// Only the total cost is recorded in a plan, so a step offered at another
+// cost shows up as a different `replayed_cost` without naming the step. Keep
+// the `step_costs` of a validation right after planning and pass them to
+// `validate_plan_costs` to find it
+pub fn validate_plan<'a>(
+    start: &State,
+    actions: &[Box<dyn Action + 'a>],
+    plan: &Plan,
+    goal: &Goal,
+) -> PlanValidation {
+    validate_plan_with(start, actions, plan, goal, &PlanOptions::new())
+}
+
+// Replays from the start prepared with the schema of the options, like the
+// plan was planned
+pub fn validate_plan_with<'a>(
+    start: &State,
+    actions: &[Box<dyn Action + 'a>],
+    plan: &Plan,
+    goal: &Goal,
+    options: &PlanOptions,
+) -> PlanValidation {
+    validate(start, actions, plan, goal, options, None)
+}
+
+// Like `validate_plan_with`, also reporting the first step whose cost
+// differs from `recorded_step_costs`, one cost per step after the start
+pub fn validate_plan_costs<'a>(
+    start: &State,
+    actions: &[Box<dyn Action + 'a>],
+    plan: &Plan,
+    recorded_step_costs: &[u64],
+    goal: &Goal,
+    options: &PlanOptions,
+) -> PlanValidation {
+    validate(
+        start,
+        actions,
+        plan,
+        goal,
+        options,
+        Some(recorded_step_costs),
+    )
+}
+
+fn validate<'a>(
+    start: &State,
+    actions: &[Box<dyn Action + 'a>],
+    plan: &Plan,
+    goal: &Goal,
+    options: &PlanOptions,
+    recorded_step_costs: Option<&[u64]>,
+) -> PlanValidation {
+    let (nodes, recorded_cost) = plan;
+    let mut state = prepare_with(start, actions, options);
+    let mut replayed_cost = 0;
+    let mut step_costs = vec![];
+    let mut invalid_step = None;
+
+    for (step, node) in nodes.iter().enumerate().skip(1) {
+        let consequence = match node {
+            Node::Consequence(consequence) => consequence,
+            Node::State(_) => {
+                invalid_step = Some(InvalidStep {
+                    step,
+                    reason: InvalidReason::NotAConsequence,
+                });
+                break;
+            }
+        };
+        let recorded = recorded_step_costs.and_then(|costs| costs.get(step - 1).copied());
+        match (replay_step(&state, actions, consequence), recorded) {
+            (Ok(cost), Some(recorded)) if recorded != cost => {
+                invalid_step = Some(InvalidStep {
+                    step,
+                    reason: InvalidReason::CostChanged {
+                        recorded,
+                        replayed: cost,
+                    },
+                });
+                break;
+            }
+            (Ok(cost), _) => {
+                replayed_cost += cost;
+                step_costs.push(cost);
+                state = consequence.result.clone();
+            }
+            (Err(reason), _) => {
+                invalid_step = Some(InvalidStep { step, reason });
+                break;
+            }
+        }
+    }
+
+    let goal_satisfied = state.distance_to_goal(goal) == 0;
+    PlanValidation {
+        invalid_step,
+        recorded_cost: *recorded_cost,
+        replayed_cost,
+        step_costs,
+        final_state: state,
+        goal_satisfied,
+    }
+}
+
+// Cheapest cost at which `consequence` is still offered from `state`
+pub fn replay_step<'a>(
+    state: &State,
+    actions: &[Box<dyn Action + 'a>],
+    consequence: &Consequence,
+) -> Result<u64, InvalidReason> {
+    let action = actions
+        .iter()
+        .find(|action| action.key() == consequence.action)
+        .ok_or_else(|| InvalidReason::UnknownAction(consequence.action.clone()))?;
+    action
+        .options(state)
+        .into_iter()
+        .filter(|(offered, _)| same_consequence(offered, consequence))
+        .map(|(_, cost)| cost)
+        .min()
+        .ok_or(InvalidReason::NotOffered)
+}
+
+// Unlike `Consequence::eq` this also compares the action and argument
+// and requires the resulting fields to be identical
+pub fn same_consequence(this: &Consequence, other: &Consequence) -> bool {
+    this.action == other.action
+        && this.argument == other.argument
+        && this.result.iter().eq(other.result.iter())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        field::{Field, FieldType},
+        partial_order::{deorder, deorder_with},
+        planner::plan_with,
+        schema::{KeySchema, Schema},
+        test_support::{equals, Add},
+    };
+
+    #[test]
+    fn names_the_step_whose_cost_changed() {
+        let start = State::new().with_field("x", Field::from(0u64));
+        let goal = equals("x", 2);
+        let planned: Vec<Box<dyn Action>> = vec![Box::new(Add(1))];
+        let plan = crate::planner::plan(&start, &planned, &goal).unwrap();
+        let recorded = validate_plan(&start, &planned, &plan, &goal);
+        assert!(recorded.is_valid());
+        assert_eq!(recorded.step_costs, vec![1, 1]);
+
+        let changed: Vec<Box<dyn Action>> = vec![Box::new(Add(3))];
+        let validation = validate_plan(&start, &changed, &plan, &goal);
+        assert!(!validation.is_valid());
+        assert!(validation.invalid_step.is_none());
+        assert_eq!(validation.replayed_cost, 6);
+
+        let validation = validate_plan_costs(
+            &start,
+            &changed,
+            &plan,
+            &recorded.step_costs,
+            &goal,
+            &PlanOptions::new(),
+        );
+        let invalid = validation.invalid_step.unwrap();
+        assert_eq!(invalid.step, 1);
+        assert!(matches!(
+            invalid.reason,
+            InvalidReason::CostChanged {
+                recorded: 1,
+                replayed: 3
+            }
+        ));
+    }
+
+    #[test]
+    fn plans_are_replayed_with_the_schema_they_were_planned_with() {
+        let start = State::new();
+        let actions: Vec<Box<dyn Action>> = vec![Box::new(Add(1))];
+        let schema = Schema::new().with_key(
+            "x",
+            KeySchema::new(FieldType::U64)
+                .with_default(Field::from(0u64))
+                .with_bounds(None, Some(3.0)),
+        );
+        let options = PlanOptions::new().with_schema(schema);
+        let plan = plan_with(&start, &actions, &equals("x", 2), &options).unwrap();
+
+        assert!(validate_plan_with(&start, &actions, &plan, &equals("x", 2), &options).is_valid());
+        assert!(!validate_plan(&start, &actions, &plan, &equals("x", 2)).is_valid());
+        assert!(deorder_with(&start, &actions, &plan, &options).is_some());
+        assert!(deorder(&start, &actions, &plan).is_none());
+
+        // Out of the schema's bounds, and a key it doesn't declare
+        assert!(plan_with(&start, &actions, &equals("x", 4), &options).is_none());
+        assert!(plan_with(&start, &actions, &equals("y", 0), &options).is_none());
+    }
+}
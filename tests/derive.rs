@@ -0,0 +1,77 @@
+#![cfg(feature = "derive")]
+
+use soap::{convert::GoapState, field::Field, state::State};
+
+#[derive(Debug, Default, PartialEq, GoapState)]
+struct Tools {
+    axe: bool,
+    #[goap(rename = "axe_durability")]
+    durability: u32,
+}
+
+#[derive(Debug, Default, PartialEq, GoapState)]
+struct Camp {
+    wood: u64,
+    tools: Tools,
+    storage: Option<Tools>,
+    #[goap(skip)]
+    last_trade: Option<String>,
+}
+
+fn camp() -> Camp {
+    Camp {
+        wood: 4,
+        tools: Tools {
+            axe: true,
+            durability: 3,
+        },
+        storage: None,
+        last_trade: Some("wood".to_owned()),
+    }
+}
+
+#[test]
+fn nested_fields_use_dotted_keys() {
+    let state = camp().to_state();
+    let keys: Vec<&str> = state.iter().map(|(key, _)| key.as_str()).collect();
+    assert_eq!(keys, vec!["tools.axe", "tools.axe_durability", "wood"]);
+    assert_eq!(state.get("tools.axe_durability"), Some(Field::from(3u64)));
+}
+
+#[test]
+fn round_trips_without_skipped_fields() {
+    let camp = camp();
+    let read = Camp::from_state(&camp.to_state()).unwrap();
+    assert_eq!(read.last_trade, None);
+    assert_eq!(
+        read,
+        Camp {
+            last_trade: None,
+            ..camp
+        }
+    );
+
+    let stored = Camp {
+        storage: Some(Tools {
+            axe: false,
+            durability: 0,
+        }),
+        ..Camp::default()
+    };
+    let state = stored.to_state();
+    assert_eq!(state.get("storage.axe"), Some(Field::from(false)));
+    assert_eq!(Camp::from_state(&state), Some(stored));
+}
+
+#[test]
+fn values_that_dont_fit_fail_to_read() {
+    let state = camp().to_state();
+    let missing = State::new().with_field("wood", Field::from(4u64));
+    assert_eq!(Camp::from_state(&missing), None);
+
+    let too_large = state.with_field("tools.axe_durability", Field::from(u64::MAX));
+    assert_eq!(Camp::from_state(&too_large), None);
+
+    let wrong_type = state.with_field("wood", Field::from(true));
+    assert_eq!(Camp::from_state(&wrong_type), None);
+}